@@ -1,17 +1,21 @@
 use std::{
     collections::HashMap,
+    path::{Path, PathBuf},
     sync::{
         Arc,
         atomic::{AtomicBool, Ordering},
     },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-#[cfg(unix)]
-use anyhow::Result;
-use docker_api::{Docker, opts::ContainerListOpts};
-use regex::Regex;
+use anyhow::{Result, anyhow};
+use docker_api::{
+    Docker,
+    models::{ContainerStateStatusInlineItem, EventMessage, HealthStatusInlineItem},
+    opts::{ContainerListOpts, EventFilter, EventFilterType, EventsOpts},
+};
+use futures::StreamExt;
 use reqwest::Client;
-use serde::{Deserialize, Serialize};
 use tokio::{
     sync::{Mutex, RwLock},
     task::JoinHandle,
@@ -20,52 +24,238 @@ use tracing::{error, info, warn};
 
 use crate::{
     Opts,
+    buffer::{BufferCounters, RetryBuffer},
+    config::{CentralConfig, ConfigFile},
     container::{Container, ContainerStats},
+    filter::ContainerFilter,
+    host::HostStats,
+    labels::{self, LabelMapping},
+    lifecycle::ContainerEvent,
+    metric::{self, VictoriaMetric},
+    scrape::{self, ScrapeTarget},
+    telemetry::{self, AgentState, Telemetry},
 };
 
 #[cfg(unix)]
-pub fn new_docker() -> Result<Docker> {
-    Ok(Docker::unix("/var/run/docker.sock"))
+fn default_docker_host() -> String {
+    // Rootless docker listens in the user runtime directory.
+    if !Path::new("/var/run/docker.sock").exists()
+        && let Ok(runtime_dir) = std::env::var("XDG_RUNTIME_DIR")
+    {
+        let rootless_socket = Path::new(&runtime_dir).join("docker.sock");
+        if rootless_socket.exists() {
+            return format!("unix://{}", rootless_socket.display());
+        }
+    }
+
+    "unix:///var/run/docker.sock".to_string()
 }
 
 #[cfg(not(unix))]
-pub fn new_docker() -> Result<Docker> {
-    Docker::new("tcp://127.0.0.1:8080")
+fn default_docker_host() -> String {
+    "tcp://127.0.0.1:8080".to_string()
+}
+
+/// Connect to the docker endpoint, following the `DOCKER_HOST`, `DOCKER_CERT_PATH`
+/// and `DOCKER_TLS_VERIFY` conventions of the docker CLI.
+pub fn new_docker(opts: &Opts) -> Result<Docker> {
+    let host = opts
+        .docker_host
+        .clone()
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(default_docker_host);
+
+    let tls_verify = opts
+        .docker_tls_verify
+        .as_deref()
+        .is_some_and(|verify| !verify.is_empty() && verify != "0" && verify != "false");
+
+    // A bare path is a unix socket.
+    if host.starts_with('/') {
+        return Ok(Docker::new(format!("unix://{}", host))?);
+    }
+
+    if let Some(address) = host.strip_prefix("tcp://")
+        && (tls_verify || opts.docker_cert_path.is_some())
+    {
+        let cert_path = match &opts.docker_cert_path {
+            Some(cert_path) => cert_path.clone(),
+            None => std::env::var("HOME")
+                .map(|home| Path::new(&home).join(".docker"))
+                .map_err(|_| anyhow!("DOCKER_CERT_PATH is required to use TLS"))?,
+        };
+
+        info!(
+            "Connecting to docker on {} with TLS certificates from {}",
+            host,
+            cert_path.display()
+        );
+        return Ok(Docker::tls(address, cert_path, tls_verify)?);
+    }
+
+    info!("Connecting to docker on {}", host);
+    Ok(Docker::new(host)?)
+}
+
+// Interval between two checks of the config file modification time.
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// Docker sends the stats a bit more or less than every second, samples this close to the interval are kept.
+const STATS_INTERVAL_SLACK: Duration = Duration::from_millis(250);
+
+// Longest wait before reopening the stats stream of a container after repeated errors.
+const MAX_STATS_BACKOFF: Duration = Duration::from_secs(60);
+
+// Header where the agent reports the central configuration revision it runs.
+const CONFIG_REVISION_HEADER: &str = "X-Config-Revision";
+
+/// Options that can change while the agent runs, replaced as a whole when the config is reloaded.
+pub struct Settings {
+    pub opts: Opts,
+    pub filter: ContainerFilter,
+    pub label_mappings: Vec<LabelMapping>,
+}
+
+impl Settings {
+    /// Validate the options, so a bad config is refused before replacing the current one.
+    pub fn new(opts: Opts) -> Result<Self> {
+        if opts.apikey.is_empty() {
+            return Err(anyhow!(
+                "An API key is required, set API_KEY or apikey in the config file"
+            ));
+        }
+
+        // A zero interval would turn the loops sleeping on it into busy loops.
+        for (name, interval) in [
+            ("stats_interval", opts.stats_interval),
+            ("reinspect_interval", opts.reinspect_interval),
+            ("scrape_interval", opts.scrape_interval),
+            ("resync_interval", opts.resync_interval),
+            ("central_config_interval", opts.central_config_interval),
+        ] {
+            if interval == 0 {
+                return Err(anyhow!("{} must be at least 1 second", name));
+            }
+        }
+
+        let filter = ContainerFilter::new(&opts)?;
+        let label_mappings = opts
+            .labels
+            .iter()
+            .map(|mapping| mapping.parse())
+            .collect::<Result<Vec<LabelMapping>>>()?;
+
+        Ok(Self {
+            opts,
+            filter,
+            label_mappings,
+        })
+    }
 }
 
 pub struct Agent {
+    // Options given on the command line, the config file then the central configuration are applied on top of them.
     opts: Opts,
+    settings: RwLock<Arc<Settings>>,
+    // Last central configuration applied.
+    central_config: RwLock<Option<CentralConfig>>,
     docker: Docker,
     client: Client,
+    hostname: String,
 
     is_shutting_down: AtomicBool,
 
     refresh_thread: RwLock<Option<JoinHandle<()>>>,
+    events_thread: RwLock<Option<JoinHandle<()>>>,
+    upload_thread: RwLock<Option<JoinHandle<()>>>,
+    host_thread: RwLock<Option<JoinHandle<()>>>,
+    scrape_thread: RwLock<Option<JoinHandle<()>>>,
+    telemetry_thread: RwLock<Option<JoinHandle<()>>>,
+    config_thread: RwLock<Option<JoinHandle<()>>>,
+    central_config_thread: RwLock<Option<JoinHandle<()>>>,
 
     containers: RwLock<HashMap<String, Arc<RwLock<Container>>>>,
     container_processors: RwLock<HashMap<String, JoinHandle<()>>>,
 
-    error_lock: Mutex<()>,
+    // Metrics collected by every container processor since the last upload.
+    pending_metrics: Mutex<Vec<VictoriaMetric>>,
+    // Container lifecycle events not sent yet.
+    pending_events: Mutex<Vec<ContainerEvent>>,
+    // Batches that could not be sent, replayed once the API answers again.
+    retry_buffer: Option<Mutex<RetryBuffer>>,
+    // Counters of the retry buffer, read without its lock, held during the uploads.
+    buffer_counters: Option<Arc<BufferCounters>>,
+
+    telemetry: Telemetry,
 }
 
 impl Agent {
     pub async fn start(opts: Opts) -> Result<Arc<Self>> {
-        let docker = self::new_docker()?;
-        let client = reqwest::Client::builder().build()?;
+        // Until the API answers, start with the central configuration of the last run.
+        let central_config = match (&opts.central_config_cache, opts.central_config) {
+            (Some(path), true) => CentralConfig::load_cache(path).await,
+            _ => None,
+        };
+        let settings = Self::load_settings(&opts, central_config.as_ref()).await?;
+
+        let docker = self::new_docker(&opts)?;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(opts.api_timeout))
+            .build()?;
+
+        let hostname = match &opts.hostname {
+            Some(hostname) => hostname.clone(),
+            None => docker
+                .info()
+                .await?
+                .name
+                .unwrap_or_else(|| "unknown".to_string()),
+        };
+
+        let retry_buffer = match &opts.buffer_dir {
+            Some(dir) => Some(Mutex::new(
+                RetryBuffer::open(
+                    dir,
+                    opts.buffer_max_bytes,
+                    Duration::from_secs(opts.buffer_max_age),
+                )
+                .await?,
+            )),
+            None => None,
+        };
+        let buffer_counters = match &retry_buffer {
+            Some(retry_buffer) => Some(retry_buffer.lock().await.counters()),
+            None => None,
+        };
 
         let agent = Arc::new(Self {
             opts,
+            settings: RwLock::new(Arc::new(settings)),
+            central_config: RwLock::new(central_config),
             docker,
             client,
+            hostname,
 
             is_shutting_down: AtomicBool::new(false),
 
             refresh_thread: RwLock::new(None),
+            events_thread: RwLock::new(None),
+            upload_thread: RwLock::new(None),
+            host_thread: RwLock::new(None),
+            scrape_thread: RwLock::new(None),
+            telemetry_thread: RwLock::new(None),
+            config_thread: RwLock::new(None),
+            central_config_thread: RwLock::new(None),
 
             containers: RwLock::new(HashMap::new()),
             container_processors: RwLock::new(HashMap::new()),
 
-            error_lock: Mutex::new(()),
+            pending_metrics: Mutex::new(Vec::new()),
+            pending_events: Mutex::new(Vec::new()),
+            retry_buffer,
+            buffer_counters,
+
+            telemetry: Telemetry::new(),
         });
 
         agent.refresh_containers().await?;
@@ -79,28 +269,262 @@ impl Agent {
                 agent_clone.automatic_refresh().await;
             }));
 
+        let agent_clone = Arc::clone(&agent);
+        agent
+            .events_thread
+            .write()
+            .await
+            .replace(tokio::spawn(async move {
+                agent_clone.watch_events().await;
+            }));
+
+        let agent_clone = Arc::clone(&agent);
+        agent
+            .upload_thread
+            .write()
+            .await
+            .replace(tokio::spawn(async move {
+                agent_clone.automatic_upload().await;
+            }));
+
+        agent.update_collectors().await;
+
+        if let Some(address) = agent.opts.listen {
+            let agent_clone = Arc::clone(&agent);
+            agent
+                .telemetry_thread
+                .write()
+                .await
+                .replace(tokio::spawn(async move {
+                    if let Err(e) = telemetry::serve(agent_clone, address).await {
+                        error!("Telemetry listener stopped: {:?}", e);
+                    }
+                }));
+        }
+
+        if let Some(path) = agent.opts.config.clone() {
+            let agent_clone = Arc::clone(&agent);
+            agent
+                .config_thread
+                .write()
+                .await
+                .replace(tokio::spawn(async move {
+                    agent_clone.watch_config(path).await;
+                }));
+        }
+
+        if agent.opts.central_config {
+            let agent_clone = Arc::clone(&agent);
+            agent
+                .central_config_thread
+                .write()
+                .await
+                .replace(tokio::spawn(async move {
+                    agent_clone.watch_central_config().await;
+                }));
+        }
+
         Ok(agent)
     }
-    pub fn is_excluded(self: &Arc<Self>, container: &Container) -> bool {
-        match self.opts.exclude.clone() {
-            None => {
-                return false;
+
+    /// Build the settings from the command line options, the config file and the central configuration.
+    async fn load_settings(
+        opts: &Opts,
+        central_config: Option<&CentralConfig>,
+    ) -> Result<Settings> {
+        let mut opts = opts.clone();
+
+        if let Some(path) = &opts.config {
+            ConfigFile::load(path).await?.apply(&mut opts);
+        }
+        if let Some(central_config) = central_config {
+            central_config.config.apply(&mut opts);
+        }
+
+        Settings::new(opts)
+    }
+
+    pub async fn settings(&self) -> Arc<Settings> {
+        Arc::clone(&*self.settings.read().await)
+    }
+
+    /// Replace the settings, then follow the new filters and collectors.
+    /// Processors of the containers still monitored keep running.
+    pub async fn apply_settings(self: &Arc<Self>, settings: Settings) -> Result<()> {
+        *self.settings.write().await = Arc::new(settings);
+
+        self.update_collectors().await;
+
+        // Stop the containers excluded by the new filters, the refresh adds the newly included ones.
+        let containers = self
+            .containers
+            .read()
+            .await
+            .iter()
+            .map(|(id, container)| (id.clone(), Arc::clone(container)))
+            .collect::<Vec<_>>();
+
+        for (id, container) in containers {
+            let excluded = self.is_excluded(&*container.read().await).await;
+            if excluded {
+                self.remove_container(&id).await;
             }
-            Some(filter) => {
-                let re = Regex::new(&filter);
-                match re {
-                    Err(_) => {
-                        return false;
-                    }
-                    Ok(regex) => {
-                        if regex.is_match(&container.state().to_string().to_lowercase()) {
-                            return true;
-                        }
-                    }
-                }
-                return false;
+        }
+
+        self.refresh_containers().await
+    }
+
+    /// Start or stop the host and scrape collectors to follow the settings.
+    async fn update_collectors(self: &Arc<Self>) {
+        let settings = self.settings().await;
+
+        {
+            let mut host_thread_lock = self.host_thread.write().await;
+            let running = host_thread_lock
+                .as_ref()
+                .is_some_and(|handle| !handle.is_finished());
+
+            if settings.opts.host_metrics && !running {
+                let agent_clone = Arc::clone(self);
+                host_thread_lock.replace(tokio::spawn(async move {
+                    agent_clone.process_host().await;
+                }));
+            } else if !settings.opts.host_metrics
+                && let Some(handle) = host_thread_lock.take()
+            {
+                info!("Stopped host metrics collection");
+                handle.abort();
+            }
+        }
+
+        {
+            let mut scrape_thread_lock = self.scrape_thread.write().await;
+            let running = scrape_thread_lock
+                .as_ref()
+                .is_some_and(|handle| !handle.is_finished());
+
+            if settings.opts.scrape && !running {
+                let agent_clone = Arc::clone(self);
+                scrape_thread_lock.replace(tokio::spawn(async move {
+                    agent_clone.automatic_scrape().await;
+                }));
+            } else if !settings.opts.scrape
+                && let Some(handle) = scrape_thread_lock.take()
+            {
+                info!("Stopped scraping containers");
+                handle.abort();
+            }
+        }
+    }
+
+    /// Reload the config file when its modification time changes.
+    async fn watch_config(self: Arc<Self>, path: PathBuf) {
+        let modified = async |path: &Path| {
+            tokio::fs::metadata(path)
+                .await
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        };
+
+        let mut last_modified = modified(&path).await;
+
+        loop {
+            tokio::time::sleep(CONFIG_POLL_INTERVAL).await;
+
+            let current_modified = modified(&path).await;
+            if current_modified == last_modified {
+                continue;
+            }
+            last_modified = current_modified;
+
+            match self.reload_settings().await {
+                Ok(()) => info!("Reloaded config file {}", path.display()),
+                Err(e) => error!(
+                    "Could not reload config file, keeping the current settings :{:?}",
+                    e
+                ),
+            }
+        }
+    }
+
+    async fn reload_settings(self: &Arc<Self>) -> Result<()> {
+        let settings = {
+            let central_config = self.central_config.read().await;
+            Self::load_settings(&self.opts, central_config.as_ref()).await?
+        };
+
+        self.apply_settings(settings).await
+    }
+
+    pub async fn config_revision(&self) -> i64 {
+        self.central_config
+            .read()
+            .await
+            .as_ref()
+            .map(|central_config| central_config.revision)
+            .unwrap_or(0)
+    }
+
+    /// Poll the API for a new revision of the central configuration.
+    async fn watch_central_config(self: Arc<Self>) {
+        let poll_interval = Duration::from_secs(self.opts.central_config_interval);
+
+        loop {
+            if let Err(e) = self.update_central_config().await {
+                warn!("Could not update the central configuration :{:?}", e);
             }
+
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+
+    async fn update_central_config(self: &Arc<Self>) -> Result<()> {
+        let settings = self.settings().await;
+        let running_revision = self.config_revision().await;
+
+        let url = format!("{}/agent-config", settings.opts.url);
+        let central_config = self
+            .client
+            .get(url)
+            .header("Authorization", format!("Bearer {}", settings.opts.apikey))
+            .header(CONFIG_REVISION_HEADER, running_revision)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<CentralConfig>()
+            .await?;
+
+        if central_config.revision == running_revision {
+            return Ok(());
+        }
+
+        let new_settings = Self::load_settings(&self.opts, Some(&central_config))
+            .await
+            .map_err(|e| {
+                anyhow!(
+                    "Invalid central configuration revision {}, keeping revision {} :{:?}",
+                    central_config.revision,
+                    running_revision,
+                    e
+                )
+            })?;
+
+        if let Some(path) = &self.opts.central_config_cache
+            && let Err(e) = central_config.save_cache(path).await
+        {
+            warn!("{:?}", e);
         }
+
+        info!(
+            "Applying central configuration revision {}",
+            central_config.revision
+        );
+        self.central_config.write().await.replace(central_config);
+        self.apply_settings(new_settings).await
+    }
+
+    pub async fn is_excluded(&self, container: &Container) -> bool {
+        self.settings.read().await.filter.is_excluded(container)
     }
     pub async fn refresh_containers(self: &Arc<Self>) -> Result<()> {
         let opts = ContainerListOpts::builder().all(true).build();
@@ -131,71 +555,86 @@ impl Agent {
         }
 
         // Remove containers.
-        {
-            let mut containers_lock = self.containers.write().await;
-            let mut processors_lock = self.container_processors.write().await;
+        for id in containers_to_remove {
+            self.remove_container(&id).await;
+        }
 
-            for id in containers_to_remove {
-                let container = containers_lock.remove(&id);
-                if let Some(container) = container {
-                    let container_lock = container.read().await;
+        // Add containers, one that can't be inspected (removed since the listing...) doesn't stop the others.
+        for id in containers_to_add {
+            if let Err(e) = self.track_container(&id).await {
+                warn!("Error adding container {}: {}", id, e);
+            }
+        }
 
-                    info!(
-                        "Removed container \"{}\" [{}]",
-                        container_lock.name(),
-                        container_lock.id()
-                    );
-                }
+        Ok(())
+    }
 
-                // Stop the processor.
-                if let Some(handle) = processors_lock.remove(&id) {
-                    handle.abort();
-                }
-            }
+    /// Inspect a container, then start processing it, or stop processing it when it is excluded.
+    async fn track_container(self: &Arc<Self>, id: &str) -> Result<()> {
+        let container = Container::new(&self.docker, id).await?;
+
+        if self.is_excluded(&container).await {
+            self.remove_container(id).await;
+            return Ok(());
         }
 
-        // Add containers.
-        for id in containers_to_add {
-            let container = Container::new(&self.docker, &id).await?;
+        let container_id = container.id().to_string();
 
-            if (self.is_excluded(&container)) {
-                continue;
-            }
-            info!(
-                "Added container \"{}\" [{}]",
-                container.name(),
-                container.id()
-            );
+        let mut containers_lock = self.containers.write().await;
+        let mut processors_lock = self.container_processors.write().await;
 
-            let container_id = container.id().to_string();
+        // Already tracked, or added by an event and a full refresh at the same time: keep the fresh inspection.
+        if let Some(tracked) = containers_lock.get(&container_id) {
+            tracked.write().await.update(container);
+            return Ok(());
+        }
 
-            let container_rc = Arc::new(RwLock::new(container));
-            self.containers
-                .write()
-                .await
-                .insert(container_id.clone(), Arc::clone(&container_rc));
+        info!(
+            "Added container \"{}\" [{}]",
+            container.name(),
+            container.id()
+        );
 
-            let agent_clone = Arc::clone(&self);
-            let container_clone = Arc::clone(&container_rc);
+        let container_rc = Arc::new(RwLock::new(container));
+        containers_lock.insert(container_id.clone(), Arc::clone(&container_rc));
 
-            // Spawn a task to process the container.
-            let handle = tokio::spawn(async move {
-                let _ = agent_clone.process_container(container_clone).await;
-            });
+        let agent_clone = Arc::clone(self);
 
-            self.container_processors
-                .write()
-                .await
-                .insert(container_id.clone(), handle);
-        }
+        // Spawn a task to process the container.
+        let handle = tokio::spawn(async move {
+            agent_clone.process_container(container_rc).await;
+        });
+
+        processors_lock.insert(container_id, handle);
 
         Ok(())
     }
 
-    async fn automatic_refresh(self: Arc<Self>) {
-        let refresh_interval = std::time::Duration::from_secs(3);
+    async fn remove_container(&self, id: &str) {
+        let mut containers_lock = self.containers.write().await;
+        let mut processors_lock = self.container_processors.write().await;
+
+        let container = containers_lock.remove(id);
+        if let Some(container) = container {
+            let container_lock = container.read().await;
+
+            info!(
+                "Removed container \"{}\" [{}]",
+                container_lock.name(),
+                container_lock.id()
+            );
+        }
+
+        // Stop the processor.
+        if let Some(handle) = processors_lock.remove(id) {
+            handle.abort();
+        }
+    }
 
+    /// Full refresh of the container list, to catch anything missed by the events stream.
+    async fn automatic_refresh(self: Arc<Self>) {
         loop {
+            let refresh_interval = Duration::from_secs(self.settings().await.opts.resync_interval);
             tokio::time::sleep(refresh_interval).await;
             if let Err(e) = self.refresh_containers().await {
                 warn!("Error refreshing containers: {}", e);
@@ -203,29 +642,178 @@ impl Agent {
         }
     }
 
-    async fn process_container(&self, container: Arc<RwLock<Container>>) -> Result<()> {
-        let mut previous_stats = None;
+    /// Follow the Docker events stream to add and remove containers as soon as they change.
+    async fn watch_events(self: Arc<Self>) {
+        let opts = EventsOpts::builder()
+            .filter(vec![
+                EventFilter::Type(EventFilterType::Container),
+                EventFilter::Event("create".to_string()),
+                EventFilter::Event("start".to_string()),
+                EventFilter::Event("die".to_string()),
+                EventFilter::Event("destroy".to_string()),
+                EventFilter::Event("rename".to_string()),
+                EventFilter::Event("restart".to_string()),
+                EventFilter::Event("oom".to_string()),
+                EventFilter::Event("pause".to_string()),
+                EventFilter::Event("unpause".to_string()),
+                EventFilter::Event("health_status".to_string()),
+            ])
+            .build();
+
+        loop {
+            let mut events = self.docker.events(&opts);
+
+            while let Some(event) = events.next().await {
+                match event {
+                    Ok(event) => self.handle_event(event).await,
+                    Err(e) => {
+                        warn!("Error reading docker events: {}", e);
+                        break;
+                    }
+                }
+            }
+
+            // Events may have been missed while the stream was down.
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            if let Err(e) = self.refresh_containers().await {
+                warn!("Error refreshing containers: {}", e);
+            }
+        }
+    }
+
+    async fn handle_event(self: &Arc<Self>, event: EventMessage) {
+        if let Some(container_event) = ContainerEvent::from_docker_event(&self.docker, &event).await
+        {
+            self.pending_events.lock().await.push(container_event);
+        }
+
+        let Some(id) = event.actor.and_then(|actor| actor.id) else {
+            return;
+        };
+
+        let result = match event.action.as_deref() {
+            Some("create") | Some("start") | Some("die") | Some("pause") | Some("unpause") => {
+                self.track_container(&id).await
+            }
+            Some("destroy") => {
+                self.remove_container(&id).await;
+                Ok(())
+            }
+            Some("oom") => {
+                let container = self.containers.read().await.get(&id).cloned();
+                if let Some(container) = container {
+                    container.write().await.record_oom_kill();
+                }
+                Ok(())
+            }
+            // The action is "health_status: healthy".
+            Some(action) if action == "rename" || action.starts_with("health_status") => {
+                let container = self.containers.read().await.get(&id).cloned();
+                match container {
+                    Some(container) => container.write().await.reinspect().await,
+                    None => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        };
+
+        if let Err(e) = result {
+            warn!("Error handling docker event for container {}: {}", id, e);
+        }
+    }
+
+    /// Follow the stats of a container with one long-lived docker stats stream.
+    /// The container is inspected on its docker events, and every `reinspect_interval` as a safety net.
+    /// Errors are logged and the stream is reopened with a backoff, until the shutdown or the removal of the container.
+    async fn process_container(&self, container: Arc<RwLock<Container>>) {
+        let id = container.read().await.id().to_string();
+        let docker_container = self.docker.containers().get(&id);
+
+        let mut stats_stream = docker_container.stats();
+        let mut stream_opened = Some(Instant::now());
+        let mut previous_stats: Option<ContainerStats> = None;
+        let mut last_inspection = Instant::now();
+        let mut backoff: Option<Duration> = None;
 
         while !self.is_shutting_down.load(Ordering::SeqCst) {
-            {
-                let container_lock = container.read().await;
-                let stats = container_lock.query_stats().await?;
+            let settings = self.settings().await;
+            let stats_interval = Duration::from_secs(settings.opts.stats_interval);
+            let reinspect_interval = Duration::from_secs(settings.opts.reinspect_interval);
+
+            let stats = match stats_stream.next().await {
+                Some(Ok(stats)) => {
+                    backoff = None;
+                    ContainerStats::from_json(&stats)
+                }
+                Some(Err(e)) => {
+                    if is_not_found(&e) {
+                        info!("Container {} is gone, stopping its processor", id);
+                        // Same as its destroy event, so it isn't counted nor seen as tracked until the next resync.
+                        self.remove_container(&id).await;
+                        return;
+                    }
+
+                    // Double the wait on every error in a row.
+                    let wait = backoff
+                        .map_or(stats_interval, |backoff| backoff * 2)
+                        .min(MAX_STATS_BACKOFF);
+                    backoff = Some(wait);
+                    warn!(
+                        "Error reading the stats of container {}, reopening the stream in {:?}: {}",
+                        id, wait, e
+                    );
 
-                if let Some(previous_stats) = &previous_stats {
-                    self.upload_stats(&container_lock, &stats, &previous_stats)
-                        .await?;
+                    tokio::time::sleep(wait).await;
+                    stats_stream = docker_container.stats();
+                    stream_opened = Some(Instant::now());
+                    continue;
                 }
+                None => {
+                    // The daemon closed the stream, open a new one.
+                    tokio::time::sleep(stats_interval).await;
+                    stats_stream = docker_container.stats();
+                    stream_opened = Some(Instant::now());
+                    continue;
+                }
+            };
 
-                previous_stats.replace(stats);
+            // Only the first sample of a stream waits for docker to read the stats.
+            if let Some(opened) = stream_opened.take() {
+                self.telemetry.record_stats_query(opened.elapsed());
             }
 
-            {
-                let mut container_lock = container.write().await;
-                container_lock.reinspect().await?;
+            if let Some(previous_stats) = &previous_stats {
+                let elapsed = stats.capture_time - previous_stats.capture_time;
+                if elapsed + STATS_INTERVAL_SLACK < stats_interval {
+                    continue;
+                }
+
+                if let Err(e) = self
+                    .upload_stats(&*container.read().await, &stats, previous_stats)
+                    .await
+                {
+                    warn!("Error collecting the stats of container {}: {}", id, e);
+                }
             }
-        }
 
-        Ok(())
+            previous_stats.replace(stats);
+
+            if last_inspection.elapsed() >= reinspect_interval {
+                last_inspection = Instant::now();
+                // The write lock must be released before removing the container.
+                let result = container.write().await.reinspect().await;
+                if let Err(e) = result {
+                    if e.downcast_ref::<docker_api::Error>()
+                        .is_some_and(is_not_found)
+                    {
+                        info!("Container {} is gone, stopping its processor", id);
+                        self.remove_container(&id).await;
+                        return;
+                    }
+                    warn!("Error inspecting container {}: {}", id, e);
+                }
+            }
+        }
     }
 
     async fn upload_stats(
@@ -236,89 +824,720 @@ impl Agent {
     ) -> Result<()> {
         let timestamp = stats.unix_timestamp as i64;
 
+        let elapsed = stats.capture_time - previous.capture_time;
+
         let online_cpus = stats.online_cpus as f64;
 
-        let system_cpu = (stats.system_cpu_usage - previous.system_cpu_usage) as f64;
-        let total_cpu = (stats.total_cpu_usage - previous.total_cpu_usage) as f64;
-        let kernelmode_cpu = (stats.kernelmode_cpu_usage - previous.kernelmode_cpu_usage) as f64;
-        let usermode_cpu = (stats.usermode_cpu_usage - previous.usermode_cpu_usage) as f64;
+        // Nanoseconds of cpu time used per second.
+        let system_cpu =
+            metric::counter_rate(stats.system_cpu_usage, previous.system_cpu_usage, elapsed);
+        let total_cpu =
+            metric::counter_rate(stats.total_cpu_usage, previous.total_cpu_usage, elapsed);
+        let kernelmode_cpu = metric::counter_rate(
+            stats.kernelmode_cpu_usage,
+            previous.kernelmode_cpu_usage,
+            elapsed,
+        );
+        let usermode_cpu = metric::counter_rate(
+            stats.usermode_cpu_usage,
+            previous.usermode_cpu_usage,
+            elapsed,
+        );
         let cpu_usage_percent = stats.cpu_usage_percent(previous);
 
         let memory_usage = stats.memory_usage_bytes as f64;
         let memory_limit = stats.memory_limit_bytes as f64;
         let memory_percent = stats.memory_usage_percent();
-
-        let network_rx_bytes = (stats.network_rx_bytes - previous.network_rx_bytes) as f64;
-        let network_tx_bytes = (stats.network_tx_bytes - previous.network_tx_bytes) as f64;
-
-        let futures = [
-            self.upload_stat(container, "cpu_online_cpus", online_cpus, timestamp),
-            self.upload_stat(container, "cpu_system_usage", system_cpu, timestamp),
-            self.upload_stat(container, "cpu_total_usage", total_cpu, timestamp),
-            self.upload_stat(container, "cpu_kernelmode_usage", kernelmode_cpu, timestamp),
-            self.upload_stat(container, "cpu_usermode_usage", usermode_cpu, timestamp),
-            self.upload_stat(container, "cpu_usage_percent", cpu_usage_percent, timestamp),
-            self.upload_stat(container, "memory_usage_bytes", memory_usage, timestamp),
-            self.upload_stat(container, "memory_limit_bytes", memory_limit, timestamp),
-            self.upload_stat(container, "memory_usage_percent", memory_percent, timestamp),
-            self.upload_stat(container, "network_rx_bytes", network_rx_bytes, timestamp),
-            self.upload_stat(container, "network_tx_bytes", network_tx_bytes, timestamp),
+        let memory_rss = stats.memory_rss_bytes as f64;
+        let memory_cache = stats.memory_cache_bytes as f64;
+        let memory_active_file = stats.memory_active_file_bytes as f64;
+        let memory_inactive_file = stats.memory_inactive_file_bytes as f64;
+        let memory_working_set = stats.memory_working_set_bytes() as f64;
+
+        let metrics = [
+            ("cpu_online_cpus", online_cpus),
+            ("cpu_system_usage", system_cpu),
+            ("cpu_total_usage", total_cpu),
+            ("cpu_kernelmode_usage", kernelmode_cpu),
+            ("cpu_usermode_usage", usermode_cpu),
+            ("cpu_usage_percent", cpu_usage_percent),
+            (
+                "cpu_usage_seconds_total",
+                stats.total_cpu_usage as f64 / 1e9,
+            ),
+            (
+                "cpu_kernelmode_seconds_total",
+                stats.kernelmode_cpu_usage as f64 / 1e9,
+            ),
+            (
+                "cpu_usermode_seconds_total",
+                stats.usermode_cpu_usage as f64 / 1e9,
+            ),
+            ("memory_usage_bytes", memory_usage),
+            ("memory_limit_bytes", memory_limit),
+            ("memory_usage_percent", memory_percent),
+            ("memory_rss_bytes", memory_rss),
+            ("memory_cache_bytes", memory_cache),
+            ("memory_active_file_bytes", memory_active_file),
+            ("memory_inactive_file_bytes", memory_inactive_file),
+            ("memory_working_set_bytes", memory_working_set),
         ];
 
-        futures::future::join_all(futures).await;
+        let mut metrics = metrics
+            .into_iter()
+            .map(|(stat_name, stat_value)| VictoriaMetric::new(stat_name, stat_value, timestamp))
+            .collect::<Vec<_>>();
+
+        // Only cgroup v1 reports them.
+        for (stat_name, stat_value) in [
+            ("memory_swap_bytes", stats.memory_swap_bytes),
+            ("memory_failcnt", stats.memory_failcnt),
+        ] {
+            if let Some(stat_value) = stat_value {
+                metrics.push(VictoriaMetric::new(stat_name, stat_value as f64, timestamp));
+            }
+        }
+
+        for (interface, network) in &stats.networks {
+            let previous = previous.networks.get(interface);
+
+            let network_counters = [
+                (
+                    "network_rx_bytes",
+                    network.rx_bytes,
+                    previous.map(|p| p.rx_bytes),
+                ),
+                (
+                    "network_rx_packets",
+                    network.rx_packets,
+                    previous.map(|p| p.rx_packets),
+                ),
+                (
+                    "network_rx_errors",
+                    network.rx_errors,
+                    previous.map(|p| p.rx_errors),
+                ),
+                (
+                    "network_rx_dropped",
+                    network.rx_dropped,
+                    previous.map(|p| p.rx_dropped),
+                ),
+                (
+                    "network_tx_bytes",
+                    network.tx_bytes,
+                    previous.map(|p| p.tx_bytes),
+                ),
+                (
+                    "network_tx_packets",
+                    network.tx_packets,
+                    previous.map(|p| p.tx_packets),
+                ),
+                (
+                    "network_tx_errors",
+                    network.tx_errors,
+                    previous.map(|p| p.tx_errors),
+                ),
+                (
+                    "network_tx_dropped",
+                    network.tx_dropped,
+                    previous.map(|p| p.tx_dropped),
+                ),
+            ];
+
+            for metric in counter_metrics(&network_counters, elapsed, timestamp) {
+                metrics.push(metric.with_label("interface", interface));
+            }
+        }
+
+        for (device, blkio) in &stats.blkio {
+            let previous = previous.blkio.get(device);
+
+            let blkio_counters = [
+                (
+                    "blkio_read_bytes",
+                    blkio.read_bytes,
+                    previous.map(|p| p.read_bytes),
+                ),
+                (
+                    "blkio_write_bytes",
+                    blkio.write_bytes,
+                    previous.map(|p| p.write_bytes),
+                ),
+                (
+                    "blkio_read_ops",
+                    blkio.read_ops,
+                    previous.map(|p| p.read_ops),
+                ),
+                (
+                    "blkio_write_ops",
+                    blkio.write_ops,
+                    previous.map(|p| p.write_ops),
+                ),
+            ];
+
+            for metric in counter_metrics(&blkio_counters, elapsed, timestamp) {
+                metrics.push(metric.with_label("device", device));
+            }
+        }
+
+        metrics.extend(Self::status_metrics(container, timestamp));
+
+        let settings = self.settings().await;
+        let container_labels =
+            labels::container_labels(container, &self.hostname, &settings.label_mappings);
+
+        let mut pending = self.pending_metrics.lock().await;
+        for mut metric in metrics {
+            for (name, value) in &container_labels {
+                metric = metric.with_label(name, value);
+            }
+            pending.push(metric);
+        }
 
         Ok(())
     }
 
-    async fn upload_stat(
-        &self,
-        container: &Container,
-        stat_name: &str,
-        stat_value: f64,
-        timestamp: i64,
-    ) -> Result<()> {
-        #[derive(Debug, Clone, Serialize, Deserialize)]
-        pub struct VictoriaMetric {
-            pub metric: HashMap<String, String>,
-            pub values: Vec<f64>,
-            pub timestamps: Vec<i64>,
+    async fn automatic_scrape(self: Arc<Self>) {
+        loop {
+            let scrape_interval = Duration::from_secs(self.settings().await.opts.scrape_interval);
+            tokio::time::sleep(scrape_interval).await;
+
+            let containers = self
+                .containers
+                .read()
+                .await
+                .values()
+                .cloned()
+                .collect::<Vec<_>>();
+
+            let futures = containers
+                .iter()
+                .map(|container| self.scrape_container(container));
+
+            futures::future::join_all(futures).await;
         }
+    }
 
-        let mut hash = HashMap::new();
-        hash.insert("__name__".to_string(), stat_name.to_string());
-        hash.insert("container_name".to_string(), container.name().to_string());
+    /// Scrape the Prometheus endpoint of a container, if it has one.
+    async fn scrape_container(&self, container: &RwLock<Container>) {
+        let settings = self.settings().await;
+        let (target, container_labels) = {
+            let container_lock = container.read().await;
+            (
+                ScrapeTarget::from_container(&container_lock),
+                labels::container_labels(&container_lock, &self.hostname, &settings.label_mappings),
+            )
+        };
 
-        let metric = VictoriaMetric {
-            metric: hash,
-            values: vec![stat_value],
-            timestamps: vec![timestamp],
+        let Some(target) = target else {
+            return;
         };
 
-        // let's send the new data diff to the server
-        let url = format!("{}/insert", self.opts.url);
-        let body = serde_json::to_string(&metric).unwrap();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+
+        let result = async {
+            let body = self
+                .client
+                .get(&target.url)
+                .timeout(Duration::from_secs(settings.opts.scrape_timeout))
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+
+            scrape::parse_exposition(&body, timestamp)
+        }
+        .await;
+
+        // An endpoint answering without samples is still up.
+        let (mut metrics, up) = match result {
+            Ok(metrics) => (metrics, 1.0),
+            Err(e) => {
+                warn!("Could not scrape {}: {}", target.url, e);
+                (Vec::new(), 0.0)
+            }
+        };
+        metrics.push(VictoriaMetric::new("scrape_up", up, timestamp));
+
+        let mut pending = self.pending_metrics.lock().await;
+        for mut metric in metrics {
+            for (name, value) in &container_labels {
+                // Like Prometheus, the labels of the target win and the scraped ones are renamed.
+                if let Some(scraped_value) = metric.metric.remove(name) {
+                    metric = metric.with_label(&format!("exported_{}", name), &scraped_value);
+                }
+                metric = metric.with_label(name, value);
+            }
+            pending.push(metric);
+        }
+    }
+
+    /// Collect the host metrics until the shutdown, a failed collection is logged and retried at the next interval.
+    async fn process_host(&self) {
+        let collect_interval = Duration::from_secs(1);
+        let mut previous_stats = None;
+
+        info!(
+            "Collecting host metrics of \"{}\" from {}",
+            self.hostname,
+            self.opts.proc_root.display()
+        );
 
-        let req = self
+        while !self.is_shutting_down.load(Ordering::SeqCst) {
+            let settings = self.settings().await;
+            match HostStats::query(&settings.opts.proc_root, &settings.opts.host_mounts).await {
+                Ok(stats) => {
+                    if let Some(previous_stats) = &previous_stats {
+                        self.upload_host_stats(&stats, previous_stats).await;
+                    }
+                    previous_stats.replace(stats);
+                }
+                Err(e) => warn!("Could not collect host metrics: {:?}", e),
+            }
+
+            tokio::time::sleep(collect_interval).await;
+        }
+    }
+
+    async fn upload_host_stats(&self, stats: &HostStats, previous: &HostStats) {
+        let timestamp = stats.unix_timestamp as i64;
+
+        let mut metrics = vec![
+            VictoriaMetric::new(
+                "host_cpu_usage_percent",
+                stats.cpu_usage_percent(previous),
+                timestamp,
+            ),
+            VictoriaMetric::new(
+                "host_cpu_iowait_percent",
+                stats.cpu_iowait_percent(previous),
+                timestamp,
+            ),
+            VictoriaMetric::new("host_load1", stats.load1, timestamp),
+            VictoriaMetric::new("host_load5", stats.load5, timestamp),
+            VictoriaMetric::new("host_load15", stats.load15, timestamp),
+        ];
+
+        for (stat_name, meminfo_name) in [
+            ("host_memory_total_bytes", "MemTotal"),
+            ("host_memory_free_bytes", "MemFree"),
+            ("host_memory_available_bytes", "MemAvailable"),
+            ("host_memory_buffers_bytes", "Buffers"),
+            ("host_memory_cached_bytes", "Cached"),
+            ("host_swap_total_bytes", "SwapTotal"),
+            ("host_swap_free_bytes", "SwapFree"),
+        ] {
+            if let Some(value) = stats.memory.get(meminfo_name) {
+                metrics.push(VictoriaMetric::new(stat_name, *value as f64, timestamp));
+            }
+        }
+
+        let elapsed = stats.capture_time - previous.capture_time;
+
+        for (device, disk) in &stats.disks {
+            let previous = previous.disks.get(device);
+
+            let disk_counters = [
+                (
+                    "host_disk_read_bytes",
+                    disk.read_bytes,
+                    previous.map(|p| p.read_bytes),
+                ),
+                (
+                    "host_disk_write_bytes",
+                    disk.write_bytes,
+                    previous.map(|p| p.write_bytes),
+                ),
+                (
+                    "host_disk_read_ops",
+                    disk.read_ops,
+                    previous.map(|p| p.read_ops),
+                ),
+                (
+                    "host_disk_write_ops",
+                    disk.write_ops,
+                    previous.map(|p| p.write_ops),
+                ),
+            ];
+
+            for metric in counter_metrics(&disk_counters, elapsed, timestamp) {
+                metrics.push(metric.with_label("device", device));
+            }
+        }
+
+        for (interface, network) in &stats.networks {
+            let previous = previous.networks.get(interface);
+
+            let network_counters = [
+                (
+                    "host_network_rx_bytes",
+                    network.rx_bytes,
+                    previous.map(|p| p.rx_bytes),
+                ),
+                (
+                    "host_network_rx_packets",
+                    network.rx_packets,
+                    previous.map(|p| p.rx_packets),
+                ),
+                (
+                    "host_network_rx_errors",
+                    network.rx_errors,
+                    previous.map(|p| p.rx_errors),
+                ),
+                (
+                    "host_network_rx_dropped",
+                    network.rx_dropped,
+                    previous.map(|p| p.rx_dropped),
+                ),
+                (
+                    "host_network_tx_bytes",
+                    network.tx_bytes,
+                    previous.map(|p| p.tx_bytes),
+                ),
+                (
+                    "host_network_tx_packets",
+                    network.tx_packets,
+                    previous.map(|p| p.tx_packets),
+                ),
+                (
+                    "host_network_tx_errors",
+                    network.tx_errors,
+                    previous.map(|p| p.tx_errors),
+                ),
+                (
+                    "host_network_tx_dropped",
+                    network.tx_dropped,
+                    previous.map(|p| p.tx_dropped),
+                ),
+            ];
+
+            for metric in counter_metrics(&network_counters, elapsed, timestamp) {
+                metrics.push(metric.with_label("interface", interface));
+            }
+        }
+
+        for (mountpoint, filesystem) in &stats.filesystems {
+            let filesystem_metrics = [
+                ("host_filesystem_size_bytes", filesystem.size_bytes),
+                ("host_filesystem_free_bytes", filesystem.free_bytes),
+                (
+                    "host_filesystem_available_bytes",
+                    filesystem.available_bytes,
+                ),
+            ];
+
+            for (stat_name, stat_value) in filesystem_metrics {
+                metrics.push(
+                    VictoriaMetric::new(stat_name, stat_value as f64, timestamp)
+                        .with_label("mountpoint", mountpoint),
+                );
+            }
+        }
+
+        let mut pending = self.pending_metrics.lock().await;
+        for metric in metrics {
+            pending.push(metric.with_label("host", &self.hostname));
+        }
+    }
+
+    /// Metrics about the container itself, taken from its last inspection.
+    fn status_metrics(container: &Container, timestamp: i64) -> Vec<VictoriaMetric> {
+        let mut metrics = vec![
+            VictoriaMetric::new(
+                "container_restart_count",
+                container.restart_count() as f64,
+                timestamp,
+            ),
+            VictoriaMetric::new(
+                "container_oom_killed",
+                if container.oom_killed() { 1.0 } else { 0.0 },
+                timestamp,
+            ),
+            VictoriaMetric::new(
+                "container_oom_kills_total",
+                container.oom_kills() as f64,
+                timestamp,
+            ),
+        ];
+
+        // 0 = unhealthy, 1 = healthy, 2 = starting, not sent without health check.
+        let health_status = match container.health() {
+            Some(HealthStatusInlineItem::Unhealthy) => Some(0.0),
+            Some(HealthStatusInlineItem::Healthy) => Some(1.0),
+            Some(HealthStatusInlineItem::Starting) => Some(2.0),
+            Some(HealthStatusInlineItem::None) | None => None,
+        };
+        if let Some(health_status) = health_status {
+            metrics.push(VictoriaMetric::new(
+                "container_health_status",
+                health_status,
+                timestamp,
+            ));
+        }
+
+        if let Some(uptime) = container.uptime() {
+            metrics.push(VictoriaMetric::new(
+                "container_uptime_seconds",
+                uptime.num_milliseconds() as f64 / 1000.0,
+                timestamp,
+            ));
+        }
+
+        // One series by state, only the current one is 1.
+        for state in CONTAINER_STATES {
+            let value = if *container.state() == state {
+                1.0
+            } else {
+                0.0
+            };
+            metrics.push(
+                VictoriaMetric::new("container_state", value, timestamp)
+                    .with_label("state", state.as_ref()),
+            );
+        }
+
+        metrics
+    }
+
+    async fn automatic_upload(self: Arc<Self>) {
+        let upload_interval = Duration::from_secs(1);
+
+        loop {
+            tokio::time::sleep(upload_interval).await;
+
+            self.upload_pending_events().await;
+
+            let mut metrics = std::mem::take(&mut *self.pending_metrics.lock().await);
+
+            let Some(retry_buffer) = &self.retry_buffer else {
+                if metrics.is_empty() {
+                    continue;
+                }
+
+                if let Err(e) = self.upload_batch(&metrics).await {
+                    error!(
+                        "Could not access API, dropping {} metrics and waiting 5 second :{:?}",
+                        metrics.len() - e.sent,
+                        e.error
+                    );
+
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+                continue;
+            };
+
+            let mut retry_buffer = retry_buffer.lock().await;
+            metrics.extend(
+                Self::buffer_stats(&retry_buffer)
+                    .map(|metric| metric.with_label("host", &self.hostname)),
+            );
+
+            // Older batches are sent first, so the data reach the API in order.
+            let result = match self.replay_buffer(&mut retry_buffer).await {
+                // Only the chunks the API did not store are dropped or buffered.
+                Ok(()) => self.upload_batch(&metrics).await.map_err(|e| {
+                    metrics.drain(..e.sent);
+                    e.error
+                }),
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = result {
+                if !is_retryable(&e) {
+                    error!(
+                        "API rejected {} metrics, dropping them :{:?}",
+                        metrics.len(),
+                        e
+                    );
+                    retry_buffer.record_dropped(metrics.len() as u64);
+                    continue;
+                }
+
+                error!(
+                    "Could not access API, buffering {} metrics and waiting 5 second before retrying :{:?}",
+                    metrics.len(),
+                    e
+                );
+                if let Err(e) = retry_buffer.push(&metrics).await {
+                    error!("Could not write retry buffer, dropping metrics :{:?}", e);
+                    retry_buffer.record_dropped(metrics.len() as u64);
+                }
+
+                drop(retry_buffer);
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        }
+    }
+
+    /// Send the buffered batches, oldest first, until the buffer is empty or the API fails.
+    async fn replay_buffer(&self, retry_buffer: &mut RetryBuffer) -> Result<()> {
+        while let Some(metrics) = retry_buffer.front().await? {
+            match self.upload_batch(&metrics).await {
+                Ok(()) => {
+                    info!("Replayed {} buffered metrics", metrics.len());
+                    retry_buffer.pop_front().await?;
+                }
+                Err(e) if !is_retryable(&e.error) => {
+                    error!("API rejected a buffered batch, dropping it :{:?}", e.error);
+                    retry_buffer.pop_front().await?;
+                    retry_buffer.record_dropped((metrics.len() - e.sent) as u64);
+                }
+                Err(e) => {
+                    // Keep the chunks the API did not store, the next replay starts after the others.
+                    if e.sent > 0 {
+                        retry_buffer.replace_front(&metrics[e.sent..]).await?;
+                    }
+                    return Err(e.error);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Report the state of the retry buffer with the other metrics.
+    fn buffer_stats(retry_buffer: &RetryBuffer) -> [VictoriaMetric; 2] {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+
+        [
+            VictoriaMetric::new(
+                "agent_buffer_samples",
+                retry_buffer.buffered_samples() as f64,
+                timestamp,
+            ),
+            VictoriaMetric::new(
+                "agent_buffer_dropped_samples",
+                retry_buffer.dropped_samples() as f64,
+                timestamp,
+            ),
+        ]
+    }
+
+    /// Send the lifecycle events, they are kept for the next tick when the API is unreachable.
+    async fn upload_pending_events(&self) {
+        let events = std::mem::take(&mut *self.pending_events.lock().await);
+        if events.is_empty() {
+            return;
+        }
+
+        let settings = self.settings().await;
+        let url = format!("{}/container-events", settings.opts.url);
+        let result = self
             .client
             .post(url)
-            .header("Authorization", format!("Bearer {}", self.opts.apikey))
-            .header("Content-Type", "application/json")
-            .body(body);
-
-        let err = req.send().await;
-        if let Err(e) = err {
-            error!(
-                "Could not access API, waiting 5 second before retrying :{:?}",
-                e
-            );
+            .header("Authorization", format!("Bearer {}", settings.opts.apikey))
+            .json(&events)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status());
+
+        if let Err(e) = result {
+            if e.status().is_some_and(|status| status.is_client_error()) {
+                error!(
+                    "API rejected {} container events, dropping them :{:?}",
+                    events.len(),
+                    e
+                );
+                return;
+            }
+
+            warn!("Could not send {} container events :{:?}", events.len(), e);
 
-            let _lock = self.error_lock.lock().await;
-            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            let mut pending = self.pending_events.lock().await;
+            pending.splice(0..0, events);
+            let overflow = pending.len().saturating_sub(MAX_PENDING_EVENTS);
+            pending.drain(..overflow);
+        }
+    }
+
+    /// Send the metrics of the batch to the API, in one request unless the body is bigger than `max_body_bytes`.
+    /// On failure, the error tells how many metrics the chunks sent before it stored.
+    async fn upload_batch(&self, metrics: &[VictoriaMetric]) -> Result<(), UploadError> {
+        let settings = self.settings().await;
+        let url = format!("{}/insert", settings.opts.url);
+        let compression = self.opts.compression;
+
+        let bodies = metric::to_json_lines_chunks(metrics, self.opts.max_body_bytes)
+            .map_err(|e| UploadError::new(0, e.into()))?;
+        let mut sent = 0;
+        for body in bodies {
+            // One line per metric.
+            let count = body.lines().count();
+
+            let mut request = self
+                .client
+                .post(&url)
+                .header("Authorization", format!("Bearer {}", settings.opts.apikey))
+                .header("Content-Type", "application/x-ndjson");
+            if let Some(encoding) = compression.content_encoding() {
+                request = request.header("Content-Encoding", encoding);
+            }
+
+            let body = compression
+                .compress(body.into_bytes())
+                .map_err(|e| UploadError::new(sent, e))?;
+            let result = match request.body(body).send().await {
+                Ok(response) => match response.error_for_status_ref() {
+                    Ok(_) => {
+                        // The API stores the valid samples and lists the ones it dropped.
+                        if let Ok(report) = response.json::<IngestReport>().await
+                            && report.rejected_samples > 0
+                        {
+                            warn!(
+                                "API dropped {} invalid samples, first one :{}",
+                                report.rejected_samples,
+                                report.rejected.first().unwrap_or_default()
+                            );
+                        }
+                        Ok(())
+                    }
+                    Err(e) => {
+                        // The API tells in the body why the samples were not stored.
+                        warn!(
+                            "API answered {} :{}",
+                            response.status(),
+                            response.text().await.unwrap_or_default()
+                        );
+                        Err(e)
+                    }
+                },
+                Err(e) => Err(e),
+            };
+
+            self.telemetry.record_upload(result.is_ok());
+            result.map_err(|e| UploadError::new(sent, e.into()))?;
+            sent += count;
         }
 
         Ok(())
     }
 
+    pub fn telemetry(&self) -> &Telemetry {
+        &self.telemetry
+    }
+
+    pub async fn telemetry_state(&self) -> AgentState {
+        let (buffered_samples, dropped_samples) = match &self.buffer_counters {
+            Some(counters) => (counters.buffered_samples(), counters.dropped_samples()),
+            None => (0, 0),
+        };
+
+        AgentState {
+            containers: self.containers.read().await.len(),
+            container_processors: self.container_processors.read().await.len(),
+            buffered_samples,
+            dropped_samples,
+            config_revision: self.config_revision().await,
+        }
+    }
+
     pub async fn shutdown(&self) {
         self.is_shutting_down.store(true, Ordering::SeqCst);
 
@@ -331,6 +1550,55 @@ impl Agent {
             }
         }
 
+        {
+            let mut events_thread_lock = self.events_thread.write().await;
+            if let Some(handle) = events_thread_lock.take() {
+                handles.push(handle);
+            }
+        }
+
+        {
+            let mut central_config_thread_lock = self.central_config_thread.write().await;
+            if let Some(handle) = central_config_thread_lock.take() {
+                handles.push(handle);
+            }
+        }
+
+        {
+            let mut config_thread_lock = self.config_thread.write().await;
+            if let Some(handle) = config_thread_lock.take() {
+                handles.push(handle);
+            }
+        }
+
+        {
+            let mut telemetry_thread_lock = self.telemetry_thread.write().await;
+            if let Some(handle) = telemetry_thread_lock.take() {
+                handles.push(handle);
+            }
+        }
+
+        {
+            let mut scrape_thread_lock = self.scrape_thread.write().await;
+            if let Some(handle) = scrape_thread_lock.take() {
+                handles.push(handle);
+            }
+        }
+
+        {
+            let mut host_thread_lock = self.host_thread.write().await;
+            if let Some(handle) = host_thread_lock.take() {
+                handles.push(handle);
+            }
+        }
+
+        {
+            let mut upload_thread_lock = self.upload_thread.write().await;
+            if let Some(handle) = upload_thread_lock.take() {
+                handles.push(handle);
+            }
+        }
+
         {
             let mut processors_lock = self.container_processors.write().await;
             for (_, handle) in processors_lock.drain() {
@@ -347,3 +1615,113 @@ impl Agent {
         futures::future::join_all(handles).await;
     }
 }
+
+/// Cumulative value of each counter as `<name>_total`, and its per-second rate since the previous sample as `<name>`.
+/// The rate is only sent when the counter was in the previous sample, a new interface or device has no rate yet.
+fn counter_metrics(
+    counters: &[(&str, u64, Option<u64>)],
+    elapsed: Duration,
+    timestamp: i64,
+) -> Vec<VictoriaMetric> {
+    let mut metrics = Vec::new();
+
+    for (name, current, previous) in counters {
+        metrics.push(VictoriaMetric::new(
+            &format!("{}_total", name),
+            *current as f64,
+            timestamp,
+        ));
+
+        if let Some(previous) = previous {
+            metrics.push(VictoriaMetric::new(
+                name,
+                metric::counter_rate(*current, *previous, elapsed),
+                timestamp,
+            ));
+        }
+    }
+
+    metrics
+}
+
+const CONTAINER_STATES: [ContainerStateStatusInlineItem; 7] = [
+    ContainerStateStatusInlineItem::Created,
+    ContainerStateStatusInlineItem::Running,
+    ContainerStateStatusInlineItem::Paused,
+    ContainerStateStatusInlineItem::Restarting,
+    ContainerStateStatusInlineItem::Removing,
+    ContainerStateStatusInlineItem::Exited,
+    ContainerStateStatusInlineItem::Dead,
+];
+
+/// Events kept in memory while the API is unreachable, the oldest are dropped first.
+const MAX_PENDING_EVENTS: usize = 10_000;
+
+/// Answer of the API when some samples of a batch did not pass its validation.
+#[derive(serde::Deserialize)]
+struct IngestReport {
+    rejected_samples: usize,
+    rejected: Vec<serde_json::Value>,
+}
+
+/// Failure of an upload, after `sent` metrics of the batch were stored by the API.
+#[derive(Debug)]
+struct UploadError {
+    sent: usize,
+    error: anyhow::Error,
+}
+
+impl UploadError {
+    fn new(sent: usize, error: anyhow::Error) -> Self {
+        Self { sent, error }
+    }
+}
+
+// Docker answers 404 for a container removed since it was listed.
+fn is_not_found(error: &docker_api::Error) -> bool {
+    matches!(error, docker_api::Error::Fault { code, .. } if code.as_u16() == 404)
+}
+
+/// Network errors and server errors are worth retrying, a rejected batch will never be accepted.
+fn is_retryable(error: &anyhow::Error) -> bool {
+    match error.downcast_ref::<reqwest::Error>() {
+        Some(e) => e
+            .status()
+            .is_none_or(|status| status.is_server_error() || status.as_u16() == 429),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(metrics: &[VictoriaMetric]) -> Vec<(&str, f64)> {
+        metrics
+            .iter()
+            .map(|metric| (metric.metric["__name__"].as_str(), metric.values[0]))
+            .collect()
+    }
+
+    #[test]
+    fn counter_rates_need_a_previous_sample() {
+        let metrics = counter_metrics(
+            &[
+                ("network_receive_bytes", 300, Some(100)),
+                // A new interface, only the cumulative value is sent.
+                ("network_transmit_bytes", 50, None),
+            ],
+            Duration::from_secs(2),
+            0,
+        );
+
+        assert_eq!(
+            samples(&metrics),
+            [
+                ("network_receive_bytes_total", 300.0),
+                ("network_receive_bytes", 100.0),
+                ("network_transmit_bytes_total", 50.0),
+            ]
+        );
+    }
+}
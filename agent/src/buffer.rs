@@ -0,0 +1,501 @@
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use tracing::{info, warn};
+
+use crate::metric::{self, VictoriaMetric};
+
+const SEGMENT_EXTENSION: &str = "jsonl";
+// Segments are written under this extension then renamed, a crash never leaves a partial segment.
+const TEMPORARY_EXTENSION: &str = "tmp";
+
+/// One failed batch, stored in its own file of JSON lines.
+#[derive(Debug)]
+struct Segment {
+    path: PathBuf,
+    created: SystemTime,
+    bytes: u64,
+    samples: u64,
+}
+
+/// Counters of the retry buffer, read by the telemetry without waiting for the buffer lock.
+#[derive(Debug, Default)]
+pub struct BufferCounters {
+    buffered_samples: AtomicU64,
+    dropped_samples: AtomicU64,
+}
+
+impl BufferCounters {
+    pub fn buffered_samples(&self) -> u64 {
+        self.buffered_samples.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped_samples(&self) -> u64 {
+        self.dropped_samples.load(Ordering::Relaxed)
+    }
+}
+
+/// Bounded on-disk queue of batches that could not be sent to the API.
+///
+/// Every batch is written in a segment file named after an increasing sequence number,
+/// so the batches can be replayed in order after a restart of the agent.
+/// When the queue is bigger than `max_bytes` or a segment is older than `max_age`,
+/// the oldest segments are dropped.
+#[derive(Debug)]
+pub struct RetryBuffer {
+    dir: PathBuf,
+    max_bytes: u64,
+    max_age: Duration,
+
+    next_sequence: u64,
+    segments: VecDeque<Segment>,
+
+    counters: Arc<BufferCounters>,
+}
+
+impl RetryBuffer {
+    pub async fn open(dir: &Path, max_bytes: u64, max_age: Duration) -> Result<Self> {
+        tokio::fs::create_dir_all(dir).await?;
+
+        // Reload the segments left by a previous run.
+        let mut found = Vec::new();
+        let mut entries = tokio::fs::read_dir(dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let extension = path.extension().and_then(|e| e.to_str());
+            if extension == Some(TEMPORARY_EXTENSION) {
+                // Left by a write interrupted before the rename.
+                if let Err(e) = tokio::fs::remove_file(&path).await {
+                    warn!("Failed to remove {}: {}", path.display(), e);
+                }
+                continue;
+            }
+            if extension != Some(SEGMENT_EXTENSION) {
+                continue;
+            }
+            let Some(sequence) = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse::<u64>().ok())
+            else {
+                continue;
+            };
+
+            let metadata = entry.metadata().await?;
+            // An unreadable segment is dropped by `front` when it is replayed.
+            let samples = match tokio::fs::read_to_string(&path).await {
+                Ok(content) => content.lines().count() as u64,
+                Err(_) => 0,
+            };
+
+            found.push((
+                sequence,
+                Segment {
+                    path,
+                    created: metadata.modified()?,
+                    bytes: metadata.len(),
+                    samples,
+                },
+            ));
+        }
+        found.sort_by_key(|(sequence, _)| *sequence);
+
+        let next_sequence = found.last().map(|(sequence, _)| sequence + 1).unwrap_or(0);
+        let segments = found.into_iter().map(|(_, segment)| segment).collect();
+
+        let buffer = Self {
+            dir: dir.to_path_buf(),
+            max_bytes,
+            max_age,
+
+            next_sequence,
+            segments,
+
+            counters: Arc::default(),
+        };
+        buffer.update_counters();
+
+        if !buffer.segments.is_empty() {
+            info!(
+                "Loaded {} buffered samples from {}",
+                buffer.buffered_samples(),
+                dir.display()
+            );
+        }
+
+        Ok(buffer)
+    }
+
+    /// Append a batch at the end of the queue.
+    /// A batch bigger than the whole buffer is dropped, it would evict every other batch then itself.
+    pub async fn push(&mut self, metrics: &[VictoriaMetric]) -> Result<()> {
+        let body = metric::to_json_lines(metrics)?;
+        if body.len() as u64 > self.max_bytes {
+            warn!(
+                "Batch of {} samples ({} bytes) is bigger than the retry buffer ({} bytes), dropping it",
+                metrics.len(),
+                body.len(),
+                self.max_bytes
+            );
+            self.record_dropped(metrics.len() as u64);
+            return Ok(());
+        }
+
+        let path = self
+            .dir
+            .join(format!("{:020}.{}", self.next_sequence, SEGMENT_EXTENSION));
+        let temporary = path.with_extension(TEMPORARY_EXTENSION);
+
+        tokio::fs::write(&temporary, &body).await?;
+        tokio::fs::rename(&temporary, &path).await?;
+        self.next_sequence += 1;
+
+        self.segments.push_back(Segment {
+            path,
+            created: SystemTime::now(),
+            bytes: body.len() as u64,
+            samples: metrics.len() as u64,
+        });
+
+        self.update_counters();
+
+        while self.buffered_bytes() > self.max_bytes {
+            warn!("Retry buffer is full, dropping the oldest batch");
+            self.drop_front().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Read the oldest batch still young enough to be replayed.
+    /// Segments that cannot be read are dropped so they do not block the ones after them.
+    pub async fn front(&mut self) -> Result<Option<Vec<VictoriaMetric>>> {
+        while let Some(segment) = self.segments.front() {
+            let age = segment.created.elapsed().unwrap_or_default();
+            if age > self.max_age {
+                warn!(
+                    "Buffered batch is older than {:?}, dropping it",
+                    self.max_age
+                );
+                self.drop_front().await?;
+                continue;
+            }
+
+            match read_segment(&segment.path).await {
+                Ok(metrics) => return Ok(Some(metrics)),
+                Err(e) => {
+                    warn!(
+                        "Failed to read buffered batch {}, dropping it: {}",
+                        segment.path.display(),
+                        e
+                    );
+                    self.drop_front().await?;
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Replace the oldest batch by the part of it that was not sent yet.
+    /// The segment keeps its modification time, so the rest of the batch still expires with its age.
+    pub async fn replace_front(&mut self, metrics: &[VictoriaMetric]) -> Result<()> {
+        let Some(segment) = self.segments.front_mut() else {
+            return Ok(());
+        };
+
+        let body = metric::to_json_lines(metrics)?;
+        let temporary = segment.path.with_extension(TEMPORARY_EXTENSION);
+        tokio::fs::write(&temporary, &body).await?;
+        set_modified(&temporary, segment.created).await?;
+        tokio::fs::rename(&temporary, &segment.path).await?;
+
+        segment.bytes = body.len() as u64;
+        segment.samples = metrics.len() as u64;
+        self.update_counters();
+
+        Ok(())
+    }
+
+    /// Remove the oldest batch once it has been sent.
+    pub async fn pop_front(&mut self) -> Result<()> {
+        if let Some(segment) = self.segments.pop_front() {
+            self.update_counters();
+            tokio::fs::remove_file(&segment.path).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove the oldest batch without sending it.
+    pub async fn drop_front(&mut self) -> Result<()> {
+        if let Some(segment) = self.segments.front() {
+            self.record_dropped(segment.samples);
+        }
+
+        self.pop_front().await
+    }
+
+    /// Count samples that were lost for good, because they were rejected or evicted.
+    pub fn record_dropped(&mut self, samples: u64) {
+        self.counters
+            .dropped_samples
+            .fetch_add(samples, Ordering::Relaxed);
+    }
+
+    /// Counters following the buffer, shared with the telemetry.
+    pub fn counters(&self) -> Arc<BufferCounters> {
+        Arc::clone(&self.counters)
+    }
+
+    fn update_counters(&self) {
+        self.counters
+            .buffered_samples
+            .store(self.buffered_samples(), Ordering::Relaxed);
+    }
+
+    pub fn buffered_bytes(&self) -> u64 {
+        self.segments.iter().map(|segment| segment.bytes).sum()
+    }
+
+    pub fn buffered_samples(&self) -> u64 {
+        self.segments.iter().map(|segment| segment.samples).sum()
+    }
+
+    pub fn dropped_samples(&self) -> u64 {
+        self.counters.dropped_samples()
+    }
+}
+
+// The modification time of a segment is its creation time when it is reloaded.
+async fn set_modified(path: &Path, time: SystemTime) -> Result<()> {
+    let file = tokio::fs::File::options()
+        .write(true)
+        .open(path)
+        .await?
+        .into_std()
+        .await;
+    tokio::task::spawn_blocking(move || file.set_modified(time)).await??;
+
+    Ok(())
+}
+
+async fn read_segment(path: &Path) -> Result<Vec<VictoriaMetric>> {
+    let content = tokio::fs::read_to_string(path).await?;
+    let metrics = content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<VictoriaMetric>, _>>()?;
+
+    Ok(metrics)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    // Empty directory for one test, removed when dropped.
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new() -> Self {
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "agent-buffer-test-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::SeqCst)
+            ));
+            let _ = std::fs::remove_dir_all(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn batch(name: &str, samples: usize) -> Vec<VictoriaMetric> {
+        (0..samples)
+            .map(|i| VictoriaMetric::new(name, i as f64, 1_700_000_000_000 + i as i64))
+            .collect()
+    }
+
+    fn names(metrics: &[VictoriaMetric]) -> Vec<&str> {
+        metrics
+            .iter()
+            .map(|metric| metric.metric["__name__"].as_str())
+            .collect()
+    }
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    #[tokio::test]
+    async fn push_then_front_replays_in_order() {
+        let dir = TestDir::new();
+        let mut buffer = RetryBuffer::open(&dir.0, 1 << 20, HOUR).await.unwrap();
+
+        buffer.push(&batch("first", 2)).await.unwrap();
+        buffer.push(&batch("second", 3)).await.unwrap();
+        assert_eq!(buffer.buffered_samples(), 5);
+
+        let front = buffer.front().await.unwrap().unwrap();
+        assert_eq!(names(&front), ["first", "first"]);
+        assert_eq!(front[1].values, [1.0]);
+
+        buffer.pop_front().await.unwrap();
+        let front = buffer.front().await.unwrap().unwrap();
+        assert_eq!(names(&front), ["second", "second", "second"]);
+
+        buffer.pop_front().await.unwrap();
+        assert!(buffer.front().await.unwrap().is_none());
+        assert_eq!(buffer.dropped_samples(), 0);
+        assert_eq!(buffer.counters().buffered_samples(), 0);
+    }
+
+    #[tokio::test]
+    async fn open_reloads_segments_and_removes_temporary_files() {
+        let dir = TestDir::new();
+        {
+            let mut buffer = RetryBuffer::open(&dir.0, 1 << 20, HOUR).await.unwrap();
+            buffer.push(&batch("first", 1)).await.unwrap();
+            buffer.push(&batch("second", 1)).await.unwrap();
+        }
+        std::fs::write(dir.0.join("00000000000000000002.tmp"), "partial").unwrap();
+
+        let mut buffer = RetryBuffer::open(&dir.0, 1 << 20, HOUR).await.unwrap();
+        assert_eq!(buffer.buffered_samples(), 2);
+        assert!(!dir.0.join("00000000000000000002.tmp").exists());
+
+        let front = buffer.front().await.unwrap().unwrap();
+        assert_eq!(names(&front), ["first"]);
+
+        // New segments go after the reloaded ones.
+        buffer.push(&batch("third", 1)).await.unwrap();
+        assert!(dir.0.join("00000000000000000002.jsonl").exists());
+    }
+
+    #[tokio::test]
+    async fn size_eviction_drops_oldest() {
+        let dir = TestDir::new();
+        let size = metric::to_json_lines(&batch("a", 2)).unwrap().len() as u64;
+        let mut buffer = RetryBuffer::open(&dir.0, size * 2, HOUR).await.unwrap();
+
+        buffer.push(&batch("a", 2)).await.unwrap();
+        buffer.push(&batch("b", 2)).await.unwrap();
+        buffer.push(&batch("c", 2)).await.unwrap();
+
+        assert_eq!(buffer.buffered_samples(), 4);
+        assert_eq!(buffer.dropped_samples(), 2);
+        let counters = buffer.counters();
+        assert_eq!(counters.buffered_samples(), 4);
+        assert_eq!(counters.dropped_samples(), 2);
+        let front = buffer.front().await.unwrap().unwrap();
+        assert_eq!(names(&front), ["b", "b"]);
+    }
+
+    #[tokio::test]
+    async fn batch_bigger_than_buffer_is_dropped() {
+        let dir = TestDir::new();
+        let size = metric::to_json_lines(&batch("a", 1)).unwrap().len() as u64;
+        let mut buffer = RetryBuffer::open(&dir.0, size * 2, HOUR).await.unwrap();
+
+        buffer.push(&batch("a", 1)).await.unwrap();
+        buffer.push(&batch("big", 10)).await.unwrap();
+
+        // The batch already buffered is kept.
+        assert_eq!(buffer.buffered_samples(), 1);
+        assert_eq!(buffer.dropped_samples(), 10);
+        let front = buffer.front().await.unwrap().unwrap();
+        assert_eq!(names(&front), ["a"]);
+    }
+
+    #[tokio::test]
+    async fn age_eviction_drops_old_segments() {
+        let dir = TestDir::new();
+        let mut buffer = RetryBuffer::open(&dir.0, 1 << 20, Duration::from_millis(10))
+            .await
+            .unwrap();
+
+        buffer.push(&batch("old", 2)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        buffer.push(&batch("new", 1)).await.unwrap();
+
+        let front = buffer.front().await.unwrap().unwrap();
+        assert_eq!(names(&front), ["new"]);
+        assert_eq!(buffer.dropped_samples(), 2);
+        assert_eq!(buffer.buffered_samples(), 1);
+    }
+
+    #[tokio::test]
+    async fn replace_front_keeps_the_unsent_part() {
+        let dir = TestDir::new();
+        let mut buffer = RetryBuffer::open(&dir.0, 1 << 20, HOUR).await.unwrap();
+
+        buffer.push(&batch("first", 4)).await.unwrap();
+        buffer.push(&batch("second", 1)).await.unwrap();
+        let front = buffer.front().await.unwrap().unwrap();
+        buffer.replace_front(&front[3..]).await.unwrap();
+
+        assert_eq!(buffer.buffered_samples(), 2);
+        let front = buffer.front().await.unwrap().unwrap();
+        assert_eq!(front.len(), 1);
+        assert_eq!(front[0].values, [3.0]);
+
+        // The replaced segment is still replayed first after a restart.
+        let mut buffer = RetryBuffer::open(&dir.0, 1 << 20, HOUR).await.unwrap();
+        let front = buffer.front().await.unwrap().unwrap();
+        assert_eq!(front[0].values, [3.0]);
+    }
+
+    #[tokio::test]
+    async fn replace_front_keeps_the_age() {
+        let dir = TestDir::new();
+        {
+            let mut buffer = RetryBuffer::open(&dir.0, 1 << 20, HOUR).await.unwrap();
+            buffer.push(&batch("old", 3)).await.unwrap();
+        }
+        set_modified(
+            &dir.0.join("00000000000000000000.jsonl"),
+            SystemTime::now() - HOUR / 2,
+        )
+        .await
+        .unwrap();
+
+        // Partially replayed after a restart, the rest is as old as the batch.
+        let mut buffer = RetryBuffer::open(&dir.0, 1 << 20, HOUR).await.unwrap();
+        let front = buffer.front().await.unwrap().unwrap();
+        buffer.replace_front(&front[1..]).await.unwrap();
+
+        let mut buffer = RetryBuffer::open(&dir.0, 1 << 20, HOUR / 4).await.unwrap();
+        assert!(buffer.front().await.unwrap().is_none());
+        assert_eq!(buffer.dropped_samples(), 2);
+    }
+
+    #[tokio::test]
+    async fn corrupt_segment_is_dropped() {
+        let dir = TestDir::new();
+        let mut buffer = RetryBuffer::open(&dir.0, 1 << 20, HOUR).await.unwrap();
+
+        buffer.push(&batch("first", 1)).await.unwrap();
+        buffer.push(&batch("second", 1)).await.unwrap();
+        std::fs::write(dir.0.join("00000000000000000000.jsonl"), "{not json\n").unwrap();
+
+        let front = buffer.front().await.unwrap().unwrap();
+        assert_eq!(names(&front), ["second"]);
+        assert_eq!(buffer.dropped_samples(), 1);
+        assert!(!dir.0.join("00000000000000000000.jsonl").exists());
+    }
+}
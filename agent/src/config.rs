@@ -0,0 +1,142 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::Opts;
+
+/// Options of the agent that can be set in a TOML or YAML file, and changed while the agent runs.
+///
+/// The keys have the same name as the fields of `Opts`, values in the file take precedence over
+/// the command line and the environment. Docker endpoint, listener and retry buffer are only read at startup,
+/// they are ignored in the file. Unknown keys are ignored too, so a central configuration written by a newer
+/// API does not stop older agents.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ConfigFile {
+    pub url: Option<String>,
+    pub apikey: Option<String>,
+
+    pub exclude: Option<String>,
+    pub include_name: Option<String>,
+    pub exclude_name: Option<String>,
+    pub include_image: Option<String>,
+    pub exclude_image: Option<String>,
+    pub include_labels: Option<Vec<String>>,
+    pub exclude_labels: Option<Vec<String>>,
+
+    pub labels: Option<Vec<String>>,
+
+    pub host_metrics: Option<bool>,
+    pub host_mounts: Option<Vec<PathBuf>>,
+    pub scrape: Option<bool>,
+
+    pub stats_interval: Option<u64>,
+    pub reinspect_interval: Option<u64>,
+    pub scrape_interval: Option<u64>,
+    pub scrape_timeout: Option<u64>,
+    pub resync_interval: Option<u64>,
+}
+
+/// Configuration of the agent managed in the web app, pulled from the API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CentralConfig {
+    // Increased by the API on every change, 0 when nothing is configured.
+    pub revision: i64,
+    pub config: ConfigFile,
+}
+
+impl CentralConfig {
+    /// Read the copy kept by the last run, `None` when there is none or it is unreadable.
+    pub async fn load_cache(path: &Path) -> Option<Self> {
+        let content = tokio::fs::read(path).await.ok()?;
+        serde_json::from_slice(&content).ok()
+    }
+
+    pub async fn save_cache(&self, path: &Path) -> Result<()> {
+        tokio::fs::write(path, serde_json::to_vec(self)?)
+            .await
+            .with_context(|| format!("Could not write config cache {}", path.display()))
+    }
+}
+
+impl ConfigFile {
+    /// Read the file, YAML when the extension is `.yaml` or `.yml`, TOML otherwise.
+    pub async fn load(path: &Path) -> Result<Self> {
+        let content = tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Could not read config file {}", path.display()))?;
+
+        let config = match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&content)
+                .with_context(|| format!("Invalid config file {}", path.display()))?,
+            _ => toml::from_str(&content)
+                .with_context(|| format!("Invalid config file {}", path.display()))?,
+        };
+
+        Ok(config)
+    }
+
+    /// Override the options set in the file.
+    pub fn apply(&self, opts: &mut Opts) {
+        let config = self.clone();
+
+        if let Some(url) = config.url {
+            opts.url = url;
+        }
+        if let Some(apikey) = config.apikey {
+            opts.apikey = apikey;
+        }
+
+        if config.exclude.is_some() {
+            opts.exclude = config.exclude;
+        }
+        if config.include_name.is_some() {
+            opts.include_name = config.include_name;
+        }
+        if config.exclude_name.is_some() {
+            opts.exclude_name = config.exclude_name;
+        }
+        if config.include_image.is_some() {
+            opts.include_image = config.include_image;
+        }
+        if config.exclude_image.is_some() {
+            opts.exclude_image = config.exclude_image;
+        }
+        if let Some(include_labels) = config.include_labels {
+            opts.include_labels = include_labels;
+        }
+        if let Some(exclude_labels) = config.exclude_labels {
+            opts.exclude_labels = exclude_labels;
+        }
+
+        if let Some(labels) = config.labels {
+            opts.labels = labels;
+        }
+
+        if let Some(host_metrics) = config.host_metrics {
+            opts.host_metrics = host_metrics;
+        }
+        if let Some(host_mounts) = config.host_mounts {
+            opts.host_mounts = host_mounts;
+        }
+        if let Some(scrape) = config.scrape {
+            opts.scrape = scrape;
+        }
+
+        if let Some(stats_interval) = config.stats_interval {
+            opts.stats_interval = stats_interval;
+        }
+        if let Some(reinspect_interval) = config.reinspect_interval {
+            opts.reinspect_interval = reinspect_interval;
+        }
+        if let Some(scrape_interval) = config.scrape_interval {
+            opts.scrape_interval = scrape_interval;
+        }
+        if let Some(scrape_timeout) = config.scrape_timeout {
+            opts.scrape_timeout = scrape_timeout;
+        }
+        if let Some(resync_interval) = config.resync_interval {
+            opts.resync_interval = resync_interval;
+        }
+    }
+}
@@ -1,26 +1,34 @@
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::{
+    collections::HashMap,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
 use docker_api::{
     Docker,
-    models::{ContainerInspect200Response, ContainerStateStatusInlineItem},
+    models::{ContainerInspect200Response, ContainerStateStatusInlineItem, HealthStatusInlineItem},
 };
-use futures::StreamExt;
+
+use crate::metric;
 
 pub struct Container {
     docker_container: docker_api::Container,
 
     status: ContainerStatus,
+    // OOM events seen since the agent tracks the container, docker doesn't keep a count.
+    oom_kills: u64,
 }
 
 impl Container {
-    pub async fn new(docker: &Docker, id: &String) -> Result<Self> {
+    pub async fn new(docker: &Docker, id: &str) -> Result<Self> {
         let docker_container = docker.containers().get(id);
         let status = ContainerStatus::new(&docker_container).await?;
 
         Ok(Self {
             docker_container,
             status,
+            oom_kills: 0,
         })
     }
 
@@ -30,8 +38,17 @@ impl Container {
         Ok(())
     }
 
-    pub async fn query_stats(&self) -> Result<ContainerStats> {
-        ContainerStats::query(&self.docker_container).await
+    /// Take the inspection of a newer `Container`, keeping the counts of the agent.
+    pub fn update(&mut self, container: Container) {
+        self.status = container.status;
+    }
+
+    pub fn record_oom_kill(&mut self) {
+        self.oom_kills += 1;
+    }
+
+    pub fn oom_kills(&self) -> u64 {
+        self.oom_kills
     }
 
     pub fn id(&self) -> &str {
@@ -49,22 +66,77 @@ impl Container {
     pub fn state(&self) -> &ContainerStateStatusInlineItem {
         &self.status.state
     }
+
+    pub fn labels(&self) -> &HashMap<String, String> {
+        &self.status.labels
+    }
+
+    /// IP address of the container in the given network, or in its first network.
+    pub fn ip_address(&self, network: Option<&str>) -> Option<String> {
+        let network_settings = self.status.inspection.network_settings.as_ref()?;
+
+        let mut networks = network_settings
+            .networks
+            .iter()
+            .flatten()
+            .filter(|(name, _)| network.is_none_or(|network| network == *name))
+            .collect::<Vec<_>>();
+        networks.sort_by_key(|(name, _)| *name);
+
+        networks
+            .into_iter()
+            .filter_map(|(_, endpoint)| endpoint.ip_address.clone())
+            .chain(network_settings.ip_address.clone())
+            .find(|ip_address| !ip_address.is_empty())
+    }
+
+    /// Health check status, `None` when the container has no health check.
+    pub fn health(&self) -> Option<&HealthStatusInlineItem> {
+        self.status.health.as_ref()
+    }
+
+    pub fn restart_count(&self) -> u64 {
+        self.status.restart_count
+    }
+
+    /// Whether the last exit of the container was an OOM kill.
+    pub fn oom_killed(&self) -> bool {
+        self.status
+            .inspection
+            .state
+            .as_ref()
+            .and_then(|state| state.oom_killed)
+            .unwrap_or(false)
+    }
+
+    /// Time since the container was started, `None` when it is not running.
+    pub fn uptime(&self) -> Option<chrono::Duration> {
+        if self.status.state != ContainerStateStatusInlineItem::Running {
+            return None;
+        }
+
+        self.status
+            .started_at
+            .map(|started_at| Utc::now() - started_at)
+    }
 }
 
 #[derive(Debug)]
 pub struct ContainerStatus {
-    inspection_time: Instant,
     inspection: ContainerInspect200Response,
 
     image: String,
     name: String,
     state: ContainerStateStatusInlineItem,
+    labels: HashMap<String, String>,
+    health: Option<HealthStatusInlineItem>,
+    restart_count: u64,
+    started_at: Option<DateTime<Utc>>,
 }
 
 impl ContainerStatus {
     async fn new(docker_container: &docker_api::Container) -> Result<Self> {
         let inspection = docker_container.inspect().await?;
-        let inspection_time = Instant::now();
 
         let image = inspection
             .config
@@ -95,13 +167,43 @@ impl ContainerStatus {
             _ => return Err(anyhow!("Unknown container state: {}", state_str)),
         };
 
+        let health = inspection
+            .state
+            .as_ref()
+            .and_then(|state| state.health.as_ref())
+            .and_then(|health| health.status.as_deref())
+            .and_then(|status| match status {
+                "starting" => Some(HealthStatusInlineItem::Starting),
+                "healthy" => Some(HealthStatusInlineItem::Healthy),
+                "unhealthy" => Some(HealthStatusInlineItem::Unhealthy),
+                _ => None,
+            });
+
+        let labels = inspection
+            .config
+            .as_ref()
+            .and_then(|config| config.labels.clone())
+            .unwrap_or_default();
+
+        let restart_count = inspection.restart_count.unwrap_or(0) as u64;
+
+        let started_at = inspection
+            .state
+            .as_ref()
+            .and_then(|state| state.started_at.as_deref())
+            .and_then(|started_at| DateTime::parse_from_rfc3339(started_at).ok())
+            .map(|started_at| started_at.with_timezone(&Utc));
+
         Ok(Self {
-            inspection_time,
             inspection,
 
             image,
             name,
             state,
+            labels,
+            health,
+            restart_count,
+            started_at,
         })
     }
 }
@@ -119,19 +221,45 @@ pub struct ContainerStats {
 
     pub memory_usage_bytes: u64,
     pub memory_limit_bytes: u64,
+    pub memory_rss_bytes: u64,
+    pub memory_cache_bytes: u64,
+    // Not reported with cgroup v2.
+    pub memory_swap_bytes: Option<u64>,
+    pub memory_active_file_bytes: u64,
+    pub memory_inactive_file_bytes: u64,
+    // Not reported with cgroup v2.
+    pub memory_failcnt: Option<u64>,
+
+    // Network counters, by interface name.
+    pub networks: HashMap<String, NetworkStats>,
+
+    // Block I/O counters, by device ("major:minor").
+    pub blkio: HashMap<String, BlkioStats>,
+}
 
-    pub network_rx_bytes: u64,
-    pub network_tx_bytes: u64,
+#[derive(Debug, Default, Clone)]
+pub struct NetworkStats {
+    pub rx_bytes: u64,
+    pub rx_packets: u64,
+    pub rx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_errors: u64,
+    pub tx_dropped: u64,
 }
 
-impl ContainerStats {
-    pub async fn query(docker_container: &docker_api::Container) -> Result<Self> {
-        let stats = loop {
-            if let Some(Ok(stats)) = docker_container.stats().next().await {
-                break stats;
-            }
-        };
+#[derive(Debug, Default, Clone)]
+pub struct BlkioStats {
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    pub read_ops: u64,
+    pub write_ops: u64,
+}
 
+impl ContainerStats {
+    /// Read one sample of the docker stats stream.
+    pub fn from_json(stats: &serde_json::Value) -> Self {
         let capture_time = Instant::now();
         let unix_timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -173,22 +301,106 @@ impl ContainerStats {
             .and_then(|v| v.as_u64())
             .unwrap_or(0);
 
-        // Iterate over all networks to sum rx and tx bytes.
-        let mut network_rx_bytes = 0;
-        let mut network_tx_bytes = 0;
+        let memory_stat = |name: &str| {
+            stats
+                .pointer(&format!("/memory_stats/stats/{}", name))
+                .and_then(|v| v.as_u64())
+        };
 
-        if let Some(networks) = stats.pointer("/networks").and_then(|v| v.as_object()) {
-            for (_, net_stats) in networks {
-                if let Some(rx_bytes) = net_stats.get("rx_bytes").and_then(|v| v.as_u64()) {
-                    network_rx_bytes += rx_bytes;
-                }
-                if let Some(tx_bytes) = net_stats.get("tx_bytes").and_then(|v| v.as_u64()) {
-                    network_tx_bytes += tx_bytes;
+        // cgroup v1 reports hierarchical "total_*" counters, cgroup v2 uses "anon" and "file".
+        let is_cgroup_v1 = memory_stat("total_inactive_file").is_some();
+
+        let (
+            memory_rss_bytes,
+            memory_cache_bytes,
+            memory_swap_bytes,
+            memory_active_file_bytes,
+            memory_inactive_file_bytes,
+        ) = if is_cgroup_v1 {
+            (
+                memory_stat("total_rss").or(memory_stat("rss")),
+                memory_stat("total_cache").or(memory_stat("cache")),
+                memory_stat("total_swap").or(memory_stat("swap")),
+                memory_stat("total_active_file").or(memory_stat("active_file")),
+                memory_stat("total_inactive_file"),
+            )
+        } else {
+            (
+                memory_stat("anon"),
+                memory_stat("file"),
+                None,
+                memory_stat("active_file"),
+                memory_stat("inactive_file"),
+            )
+        };
+
+        let memory_failcnt = if is_cgroup_v1 {
+            stats
+                .pointer("/memory_stats/failcnt")
+                .and_then(|v| v.as_u64())
+        } else {
+            None
+        };
+
+        // Keep the counters of every network interface.
+        let mut networks = HashMap::new();
+
+        if let Some(interfaces) = stats.pointer("/networks").and_then(|v| v.as_object()) {
+            for (interface, net_stats) in interfaces {
+                let counter =
+                    |name: &str| net_stats.get(name).and_then(|v| v.as_u64()).unwrap_or(0);
+
+                networks.insert(
+                    interface.clone(),
+                    NetworkStats {
+                        rx_bytes: counter("rx_bytes"),
+                        rx_packets: counter("rx_packets"),
+                        rx_errors: counter("rx_errors"),
+                        rx_dropped: counter("rx_dropped"),
+                        tx_bytes: counter("tx_bytes"),
+                        tx_packets: counter("tx_packets"),
+                        tx_errors: counter("tx_errors"),
+                        tx_dropped: counter("tx_dropped"),
+                    },
+                );
+            }
+        }
+
+        // Sum the read and write entries of every device.
+        // cgroup v1 reports "Read"/"Write" operations while cgroup v2 reports "read"/"write",
+        // and io_serviced_recursive is not filled with cgroup v2.
+        let mut blkio: HashMap<String, BlkioStats> = HashMap::new();
+
+        for (pointer, is_bytes) in [
+            ("/blkio_stats/io_service_bytes_recursive", true),
+            ("/blkio_stats/io_serviced_recursive", false),
+        ] {
+            let Some(entries) = stats.pointer(pointer).and_then(|v| v.as_array()) else {
+                continue;
+            };
+
+            for entry in entries {
+                let major = entry.get("major").and_then(|v| v.as_u64()).unwrap_or(0);
+                let minor = entry.get("minor").and_then(|v| v.as_u64()).unwrap_or(0);
+                let value = entry.get("value").and_then(|v| v.as_u64()).unwrap_or(0);
+                let op = entry
+                    .get("op")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .to_lowercase();
+
+                let device = blkio.entry(format!("{}:{}", major, minor)).or_default();
+                match (op.as_str(), is_bytes) {
+                    ("read", true) => device.read_bytes += value,
+                    ("write", true) => device.write_bytes += value,
+                    ("read", false) => device.read_ops += value,
+                    ("write", false) => device.write_ops += value,
+                    _ => {}
                 }
             }
         }
 
-        Ok(Self {
+        Self {
             capture_time,
             unix_timestamp,
 
@@ -200,16 +412,21 @@ impl ContainerStats {
 
             memory_usage_bytes,
             memory_limit_bytes,
+            memory_rss_bytes: memory_rss_bytes.unwrap_or(0),
+            memory_cache_bytes: memory_cache_bytes.unwrap_or(0),
+            memory_swap_bytes,
+            memory_active_file_bytes: memory_active_file_bytes.unwrap_or(0),
+            memory_inactive_file_bytes: memory_inactive_file_bytes.unwrap_or(0),
+            memory_failcnt,
 
-            network_rx_bytes,
-            network_tx_bytes,
-        })
+            networks,
+
+            blkio,
+        }
     }
 
     pub fn cpu_usage_percent(&self, previous: &ContainerStats) -> f64 {
-        let cpu_delta = self
-            .total_cpu_usage
-            .saturating_sub(previous.total_cpu_usage);
+        let cpu_delta = metric::counter_delta(self.total_cpu_usage, previous.total_cpu_usage);
 
         let system_delta = self
             .system_cpu_usage
@@ -222,11 +439,18 @@ impl ContainerStats {
         (cpu_delta as f64 / system_delta as f64) * self.online_cpus as f64 * 100.0
     }
 
+    /// Memory actually used by the container, without the page cache that can be reclaimed.
+    /// This is the value shown by `docker stats`.
+    pub fn memory_working_set_bytes(&self) -> u64 {
+        self.memory_usage_bytes
+            .saturating_sub(self.memory_inactive_file_bytes)
+    }
+
     pub fn memory_usage_percent(&self) -> f64 {
         if self.memory_limit_bytes == 0 {
             return 0.0;
         }
 
-        (self.memory_usage_bytes as f64 / self.memory_limit_bytes as f64) * 100.0
+        (self.memory_working_set_bytes() as f64 / self.memory_limit_bytes as f64) * 100.0
     }
 }
@@ -0,0 +1,240 @@
+use std::{collections::HashMap, str::FromStr};
+
+use anyhow::{Context, Result, anyhow};
+use regex::Regex;
+
+use crate::{Opts, container::Container};
+
+/// Docker label selector, `key=value` matches a label value and `key` only checks the label is present.
+#[derive(Debug, Clone)]
+pub struct LabelSelector {
+    key: String,
+    value: Option<String>,
+}
+
+impl LabelSelector {
+    pub fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        match (labels.get(&self.key), &self.value) {
+            (Some(label), Some(value)) => label == value,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+}
+
+impl FromStr for LabelSelector {
+    type Err = anyhow::Error;
+
+    fn from_str(selector: &str) -> Result<Self> {
+        let (key, value) = match selector.split_once('=') {
+            Some((key, value)) => (key.trim(), Some(value.trim().to_string())),
+            None => (selector.trim(), None),
+        };
+
+        if key.is_empty() {
+            return Err(anyhow!("Invalid label selector \"{}\"", selector));
+        }
+
+        Ok(Self {
+            key: key.to_string(),
+            value,
+        })
+    }
+}
+
+/// Rules deciding which containers are monitored.
+///
+/// A container is excluded when its state, name or image matches an exclude regex,
+/// or when any exclude label selector matches.
+/// When include rules are set, the name and image must match them and every include label selector must match.
+#[derive(Debug, Default)]
+pub struct ContainerFilter {
+    exclude_state: Option<Regex>,
+    include_name: Option<Regex>,
+    exclude_name: Option<Regex>,
+    include_image: Option<Regex>,
+    exclude_image: Option<Regex>,
+    include_labels: Vec<LabelSelector>,
+    exclude_labels: Vec<LabelSelector>,
+}
+
+impl ContainerFilter {
+    /// Compile the rules, so invalid patterns are reported at startup.
+    pub fn new(opts: &Opts) -> Result<Self> {
+        Ok(Self {
+            exclude_state: compile("exclude", &opts.exclude)?,
+            include_name: compile("include-name", &opts.include_name)?,
+            exclude_name: compile("exclude-name", &opts.exclude_name)?,
+            include_image: compile("include-image", &opts.include_image)?,
+            exclude_image: compile("exclude-image", &opts.exclude_image)?,
+            include_labels: parse_selectors(&opts.include_labels)?,
+            exclude_labels: parse_selectors(&opts.exclude_labels)?,
+        })
+    }
+
+    pub fn is_excluded(&self, container: &Container) -> bool {
+        self.excludes(
+            &container.state().to_string().to_lowercase(),
+            container.name(),
+            container.image(),
+            container.labels(),
+        )
+    }
+
+    fn excludes(
+        &self,
+        state: &str,
+        name: &str,
+        image: &str,
+        labels: &HashMap<String, String>,
+    ) -> bool {
+        let excluded = matches(&self.exclude_state, state)
+            || matches(&self.exclude_name, name)
+            || matches(&self.exclude_image, image)
+            || self
+                .exclude_labels
+                .iter()
+                .any(|selector| selector.matches(labels));
+
+        let included = self
+            .include_name
+            .as_ref()
+            .is_none_or(|regex| regex.is_match(name))
+            && self
+                .include_image
+                .as_ref()
+                .is_none_or(|regex| regex.is_match(image))
+            && self
+                .include_labels
+                .iter()
+                .all(|selector| selector.matches(labels));
+
+        excluded || !included
+    }
+}
+
+fn compile(option: &str, pattern: &Option<String>) -> Result<Option<Regex>> {
+    pattern
+        .as_deref()
+        .map(|pattern| {
+            Regex::new(pattern)
+                .with_context(|| format!("Invalid --{} regex \"{}\"", option, pattern))
+        })
+        .transpose()
+}
+
+fn parse_selectors(selectors: &[String]) -> Result<Vec<LabelSelector>> {
+    selectors.iter().map(|selector| selector.parse()).collect()
+}
+
+fn matches(regex: &Option<Regex>, value: &str) -> bool {
+    regex.as_ref().is_some_and(|regex| regex.is_match(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    fn filter(args: &[&str]) -> Result<ContainerFilter> {
+        let opts = Opts::try_parse_from(std::iter::once("agent").chain(args.iter().copied()))?;
+        ContainerFilter::new(&opts)
+    }
+
+    fn labels(labels: &[(&str, &str)]) -> HashMap<String, String> {
+        labels
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn no_rules_monitor_everything() {
+        let filter = filter(&[]).unwrap();
+
+        assert!(!filter.excludes("running", "web", "nginx:1.27", &labels(&[])));
+    }
+
+    #[test]
+    fn state_name_and_image() {
+        let filter = filter(&[
+            "--exclude",
+            "exited|dead",
+            "--include-name",
+            "^web-",
+            "--exclude-image",
+            "^busybox",
+        ])
+        .unwrap();
+        let none = labels(&[]);
+
+        assert!(!filter.excludes("running", "web-1", "nginx:1.27", &none));
+        assert!(filter.excludes("exited", "web-1", "nginx:1.27", &none));
+        assert!(filter.excludes("running", "db-1", "nginx:1.27", &none));
+        assert!(filter.excludes("running", "web-1", "busybox:latest", &none));
+    }
+
+    #[test]
+    fn exclude_wins_over_include() {
+        let filter = filter(&[
+            "--include-name",
+            "^web-",
+            "--exclude-name=-debug$",
+            "--include-label",
+            "monitor=true",
+            "--exclude-label",
+            "monitor.skip",
+        ])
+        .unwrap();
+
+        assert!(!filter.excludes("running", "web-1", "nginx", &labels(&[("monitor", "true")])));
+        assert!(filter.excludes(
+            "running",
+            "web-debug",
+            "nginx",
+            &labels(&[("monitor", "true")])
+        ));
+        assert!(filter.excludes(
+            "running",
+            "web-1",
+            "nginx",
+            &labels(&[("monitor", "true"), ("monitor.skip", "")])
+        ));
+    }
+
+    #[test]
+    fn label_selectors() {
+        let filter = filter(&["--include-label", "monitor=true,team"]).unwrap();
+
+        // Every include selector must match, `team` only needs the label to be present.
+        assert!(!filter.excludes(
+            "running",
+            "web",
+            "nginx",
+            &labels(&[("monitor", "true"), ("team", "")])
+        ));
+        assert!(filter.excludes("running", "web", "nginx", &labels(&[("monitor", "true")])));
+        assert!(filter.excludes(
+            "running",
+            "web",
+            "nginx",
+            &labels(&[("monitor", "false"), ("team", "a")])
+        ));
+    }
+
+    #[test]
+    fn invalid_rules_fail() {
+        for args in [
+            ["--exclude", "(unclosed"],
+            ["--include-name", "[a-"],
+            ["--exclude-name", "*web"],
+            ["--include-image", "(?<name"],
+            ["--exclude-image", "a{2,1}"],
+            ["--include-label", "=true"],
+            ["--exclude-label", " =x"],
+        ] {
+            assert!(filter(&args).is_err(), "{:?}", args);
+        }
+    }
+}
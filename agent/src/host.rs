@@ -0,0 +1,312 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Result, anyhow};
+use tracing::warn;
+
+use crate::container::{BlkioStats, NetworkStats};
+
+// Size of a sector in /proc/diskstats, whatever the real sector size of the device.
+const SECTOR_SIZE: u64 = 512;
+
+#[derive(Debug, Default, Clone)]
+pub struct CpuTimes {
+    pub total: u64,
+    pub idle: u64,
+    pub iowait: u64,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct FilesystemStats {
+    pub size_bytes: u64,
+    pub free_bytes: u64,
+    pub available_bytes: u64,
+}
+
+/// Metrics of the machine running the containers, read from procfs.
+#[derive(Debug)]
+pub struct HostStats {
+    pub capture_time: Instant,
+    pub unix_timestamp: u64,
+
+    pub cpu: CpuTimes,
+
+    pub load1: f64,
+    pub load5: f64,
+    pub load15: f64,
+
+    // Values of /proc/meminfo, in bytes.
+    pub memory: HashMap<String, u64>,
+
+    // Disk counters, by device name.
+    pub disks: HashMap<String, BlkioStats>,
+
+    // Network counters, by interface name.
+    pub networks: HashMap<String, NetworkStats>,
+
+    // Filesystem usage, by mount point.
+    pub filesystems: HashMap<String, FilesystemStats>,
+}
+
+impl HostStats {
+    /// Read the host stats, `proc_root` can be a bind mount of the host /proc (like /host/proc).
+    /// `mounts` are the paths where the filesystem usage is reported.
+    pub async fn query(proc_root: &Path, mounts: &[PathBuf]) -> Result<Self> {
+        let capture_time = Instant::now();
+        let unix_timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+
+        let stat = tokio::fs::read_to_string(proc_root.join("stat")).await?;
+        let cpu = parse_cpu_times(&stat)?;
+
+        let loadavg = tokio::fs::read_to_string(proc_root.join("loadavg")).await?;
+        let mut loads = loadavg
+            .split_whitespace()
+            .take(3)
+            .map(|load| load.parse::<f64>().unwrap_or(0.0));
+        let load1 = loads.next().unwrap_or(0.0);
+        let load5 = loads.next().unwrap_or(0.0);
+        let load15 = loads.next().unwrap_or(0.0);
+
+        let meminfo = tokio::fs::read_to_string(proc_root.join("meminfo")).await?;
+        let memory = parse_meminfo(&meminfo);
+
+        let diskstats = tokio::fs::read_to_string(proc_root.join("diskstats")).await?;
+        let disks = parse_diskstats(&diskstats);
+
+        // /proc/net is the network namespace of the reader, the one of pid 1 is the host network.
+        let net_dev = tokio::fs::read_to_string(proc_root.join("1/net/dev")).await?;
+        let networks = parse_net_dev(&net_dev);
+
+        let mut filesystems = HashMap::new();
+        for mount in mounts {
+            match filesystem_stats(mount) {
+                Ok(stats) => {
+                    filesystems.insert(mount.display().to_string(), stats);
+                }
+                Err(e) => warn!("Could not read filesystem {}: {}", mount.display(), e),
+            }
+        }
+
+        Ok(Self {
+            capture_time,
+            unix_timestamp,
+
+            cpu,
+
+            load1,
+            load5,
+            load15,
+
+            memory,
+            disks,
+            networks,
+            filesystems,
+        })
+    }
+
+    pub fn cpu_usage_percent(&self, previous: &HostStats) -> f64 {
+        let total_delta = self.cpu.total.saturating_sub(previous.cpu.total);
+        let idle_delta = (self.cpu.idle + self.cpu.iowait)
+            .saturating_sub(previous.cpu.idle + previous.cpu.iowait);
+
+        if total_delta == 0 {
+            return 0.0;
+        }
+
+        total_delta.saturating_sub(idle_delta) as f64 / total_delta as f64 * 100.0
+    }
+
+    pub fn cpu_iowait_percent(&self, previous: &HostStats) -> f64 {
+        let total_delta = self.cpu.total.saturating_sub(previous.cpu.total);
+        let iowait_delta = self.cpu.iowait.saturating_sub(previous.cpu.iowait);
+
+        if total_delta == 0 {
+            return 0.0;
+        }
+
+        iowait_delta as f64 / total_delta as f64 * 100.0
+    }
+}
+
+// First line of /proc/stat : "cpu user nice system idle iowait irq softirq steal guest guest_nice".
+fn parse_cpu_times(stat: &str) -> Result<CpuTimes> {
+    let line = stat
+        .lines()
+        .find(|line| line.starts_with("cpu "))
+        .ok_or_else(|| anyhow!("No cpu line in /proc/stat"))?;
+
+    let times = line
+        .split_whitespace()
+        .skip(1)
+        .map(|time| time.parse::<u64>().unwrap_or(0))
+        .collect::<Vec<_>>();
+
+    // guest and guest_nice are already counted in user and nice.
+    let total = times.iter().take(8).sum();
+
+    Ok(CpuTimes {
+        total,
+        idle: times.get(3).copied().unwrap_or(0),
+        iowait: times.get(4).copied().unwrap_or(0),
+    })
+}
+
+// Lines of /proc/meminfo : "MemTotal:       16314440 kB".
+fn parse_meminfo(meminfo: &str) -> HashMap<String, u64> {
+    meminfo
+        .lines()
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            let mut parts = value.split_whitespace();
+            let value = parts.next()?.parse::<u64>().ok()?;
+            let value = match parts.next() {
+                Some("kB") => value * 1024,
+                _ => value,
+            };
+
+            Some((name.to_string(), value))
+        })
+        .collect()
+}
+
+// Lines of /proc/diskstats : "major minor name reads merged sectors_read ms writes merged sectors_written ...".
+fn parse_diskstats(diskstats: &str) -> HashMap<String, BlkioStats> {
+    let devices = diskstats
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(2))
+        .collect::<Vec<_>>();
+
+    diskstats
+        .lines()
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let name = *fields.get(2)?;
+
+            // Virtual devices only add noise, and partitions are already counted in their disk.
+            if name.starts_with("loop")
+                || name.starts_with("ram")
+                || devices.iter().any(|disk| is_partition_of(name, disk))
+            {
+                return None;
+            }
+
+            let field = |index: usize| {
+                fields
+                    .get(index)
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .unwrap_or(0)
+            };
+
+            Some((
+                name.to_string(),
+                BlkioStats {
+                    read_ops: field(3),
+                    read_bytes: field(5) * SECTOR_SIZE,
+                    write_ops: field(7),
+                    write_bytes: field(9) * SECTOR_SIZE,
+                },
+            ))
+        })
+        .collect()
+}
+
+// Partitions are named after their disk, with the number after a `p` when the disk name ends with a digit:
+// sda1 for sda, nvme0n1p1 for nvme0n1, mmcblk0p1 for mmcblk0.
+fn is_partition_of(name: &str, disk: &str) -> bool {
+    let Some(suffix) = name.strip_prefix(disk) else {
+        return false;
+    };
+    let number = if disk.ends_with(|c: char| c.is_ascii_digit()) {
+        suffix.strip_prefix('p').unwrap_or_default()
+    } else {
+        suffix
+    };
+
+    !number.is_empty() && number.chars().all(|c| c.is_ascii_digit())
+}
+
+// /proc/net/dev has two header lines, then "iface: rx_bytes rx_packets rx_errs rx_drop fifo frame compressed multicast
+// tx_bytes tx_packets tx_errs tx_drop ...".
+fn parse_net_dev(net_dev: &str) -> HashMap<String, NetworkStats> {
+    net_dev
+        .lines()
+        .skip(2)
+        .filter_map(|line| {
+            let (interface, counters) = line.split_once(':')?;
+            let counters = counters
+                .split_whitespace()
+                .map(|v| v.parse::<u64>().unwrap_or(0))
+                .collect::<Vec<_>>();
+            let counter = |index: usize| counters.get(index).copied().unwrap_or(0);
+
+            Some((
+                interface.trim().to_string(),
+                NetworkStats {
+                    rx_bytes: counter(0),
+                    rx_packets: counter(1),
+                    rx_errors: counter(2),
+                    rx_dropped: counter(3),
+                    tx_bytes: counter(8),
+                    tx_packets: counter(9),
+                    tx_errors: counter(10),
+                    tx_dropped: counter(11),
+                },
+            ))
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn filesystem_stats(mount: &Path) -> Result<FilesystemStats> {
+    let stats = nix::sys::statvfs::statvfs(mount)?;
+    let fragment_size = stats.fragment_size() as u64;
+
+    Ok(FilesystemStats {
+        size_bytes: stats.blocks() as u64 * fragment_size,
+        free_bytes: stats.blocks_free() as u64 * fragment_size,
+        available_bytes: stats.blocks_available() as u64 * fragment_size,
+    })
+}
+
+#[cfg(not(unix))]
+fn filesystem_stats(_mount: &Path) -> Result<FilesystemStats> {
+    Err(anyhow!("Filesystem usage is only supported on unix"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diskstats_skip_partitions_and_virtual_devices() {
+        let diskstats = "\
+   7       0 loop0 10 0 20 0 0 0 0 0 0 0 0
+   8       0 sda 100 0 200 0 50 0 400 0 0 0 0
+   8       1 sda1 90 0 180 0 40 0 300 0 0 0 0
+ 259       0 nvme0n1 10 0 8 0 5 0 16 0 0 0 0
+ 259       1 nvme0n1p1 10 0 8 0 5 0 16 0 0 0 0
+ 253       1 dm-1 1 0 2 0 3 0 4 0 0 0 0
+ 253      10 dm-10 1 0 2 0 3 0 4 0 0 0 0
+ 179       0 mmcblk0 1 0 2 0 3 0 4 0 0 0 0
+ 179       1 mmcblk0p2 1 0 2 0 3 0 4 0 0 0 0
+";
+
+        let disks = parse_diskstats(diskstats);
+
+        let mut names = disks.keys().map(String::as_str).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["dm-1", "dm-10", "mmcblk0", "nvme0n1", "sda"]);
+
+        let sda = &disks["sda"];
+        assert_eq!(sda.read_ops, 100);
+        assert_eq!(sda.read_bytes, 200 * SECTOR_SIZE);
+        assert_eq!(sda.write_ops, 50);
+        assert_eq!(sda.write_bytes, 400 * SECTOR_SIZE);
+    }
+}
@@ -0,0 +1,183 @@
+use std::str::FromStr;
+
+use anyhow::{Result, anyhow};
+
+use crate::container::Container;
+
+// Labels set by docker compose on the containers it creates.
+const COMPOSE_PROJECT_LABEL: &str = "com.docker.compose.project";
+const COMPOSE_SERVICE_LABEL: &str = "com.docker.compose.service";
+
+// Metric labels set by the agent or the API, a mapping can't replace them.
+const RESERVED_LABELS: [&str; 10] = [
+    // Identity of the container and its host.
+    "host",
+    "container_id",
+    "container_name",
+    "image",
+    // Set by the API.
+    "job",
+    "exported_job",
+    // Set on the network, disk, filesystem and status metrics.
+    "interface",
+    "device",
+    "mountpoint",
+    "state",
+];
+const RESERVED_LABEL_PREFIX: &str = "compose_";
+
+/// Copy a docker label to a metric label, `docker.label=metric_label` or only `docker.label`,
+/// in which case the metric label is the docker label with invalid characters replaced by `_`.
+#[derive(Debug, Clone)]
+pub struct LabelMapping {
+    docker_label: String,
+    metric_label: String,
+}
+
+impl FromStr for LabelMapping {
+    type Err = anyhow::Error;
+
+    fn from_str(mapping: &str) -> Result<Self> {
+        let (docker_label, metric_label) = match mapping.split_once('=') {
+            Some((docker_label, metric_label)) => {
+                (docker_label.trim(), metric_label.trim().to_string())
+            }
+            None => (mapping.trim(), sanitize(mapping.trim())),
+        };
+
+        if docker_label.is_empty() || !is_valid_label_name(&metric_label) {
+            return Err(anyhow!("Invalid label mapping \"{}\"", mapping));
+        }
+        if RESERVED_LABELS.contains(&metric_label.as_str())
+            || metric_label.starts_with(RESERVED_LABEL_PREFIX)
+        {
+            return Err(anyhow!(
+                "Invalid label mapping \"{}\", the label \"{}\" is set by the agent",
+                mapping,
+                metric_label
+            ));
+        }
+
+        Ok(Self {
+            docker_label: docker_label.to_string(),
+            metric_label,
+        })
+    }
+}
+
+/// Labels identifying the series of a container, so containers with the same name on two hosts don't collide.
+pub fn container_labels(
+    container: &Container,
+    hostname: &str,
+    mappings: &[LabelMapping],
+) -> Vec<(String, String)> {
+    let mut labels = vec![
+        ("container_name".to_string(), container.name().to_string()),
+        ("container_id".to_string(), container.id().to_string()),
+        ("image".to_string(), container.image().to_string()),
+        ("host".to_string(), hostname.to_string()),
+    ];
+
+    let docker_labels = container.labels();
+
+    if let Some(project) = docker_labels.get(COMPOSE_PROJECT_LABEL) {
+        labels.push(("compose_project".to_string(), project.clone()));
+    }
+    if let Some(service) = docker_labels.get(COMPOSE_SERVICE_LABEL) {
+        labels.push(("compose_service".to_string(), service.clone()));
+    }
+
+    for mapping in mappings {
+        if let Some(value) = docker_labels.get(&mapping.docker_label) {
+            labels.push((mapping.metric_label.clone(), value.clone()));
+        }
+    }
+
+    labels
+}
+
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+// Same rule as Prometheus : [a-zA-Z_][a-zA-Z0-9_]*, names starting with "__" are reserved.
+fn is_valid_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !name.starts_with("__")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(mapping: &str) -> Result<(String, String)> {
+        let mapping = mapping.parse::<LabelMapping>()?;
+        Ok((mapping.docker_label, mapping.metric_label))
+    }
+
+    #[test]
+    fn explicit_mapping() {
+        assert_eq!(
+            mapping(" com.example.team = team ").unwrap(),
+            ("com.example.team".to_string(), "team".to_string())
+        );
+    }
+
+    #[test]
+    fn sanitized_mapping() {
+        assert_eq!(
+            mapping("com.example.team").unwrap(),
+            (
+                "com.example.team".to_string(),
+                "com_example_team".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn invalid_mappings() {
+        for invalid in [
+            "",
+            "=team",
+            "team=",
+            "team=1st",
+            "team=a-b",
+            "team=__name__",
+        ] {
+            assert!(mapping(invalid).is_err(), "mapping {:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn reserved_targets() {
+        for reserved in [
+            "owner=host",
+            "owner=container_id",
+            "owner=container_name",
+            "owner=image",
+            "owner=job",
+            "owner=exported_job",
+            "owner=interface",
+            "owner=device",
+            "owner=mountpoint",
+            "owner=state",
+            "owner=compose_project",
+            "owner=compose_anything",
+            "host",
+            "image",
+        ] {
+            assert!(mapping(reserved).is_err(), "mapping {:?}", reserved);
+        }
+
+        // Only the exact names and the compose prefix are reserved.
+        assert!(mapping("owner=hostname").is_ok());
+        assert!(mapping("owner=image_tag").is_ok());
+    }
+}
@@ -0,0 +1,77 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use docker_api::{Docker, models::EventMessage};
+use serde::{Deserialize, Serialize};
+
+/// Docker actions forwarded to the API as container lifecycle events.
+pub const LIFECYCLE_ACTIONS: [&str; 6] = ["create", "start", "restart", "die", "oom", "destroy"];
+
+// Same format as the backend `NewContainerEvent`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContainerEvent {
+    pub container_id: String,
+    pub container_name: String,
+    pub image: String,
+    pub action: String,
+    pub exit_code: Option<i64>,
+    pub oom_killed: Option<bool>,
+    pub timestamp: i64,
+}
+
+impl ContainerEvent {
+    /// Build a lifecycle event from a docker event.
+    /// When the container stopped, it is inspected to get its exit code and whether it was OOM killed.
+    pub async fn from_docker_event(docker: &Docker, event: &EventMessage) -> Option<Self> {
+        let action = event.action.clone()?;
+        if !LIFECYCLE_ACTIONS.contains(&action.as_str()) {
+            return None;
+        }
+
+        let actor = event.actor.as_ref()?;
+        let container_id = actor.id.clone()?;
+        let attribute = |name: &str| {
+            actor
+                .attributes
+                .as_ref()
+                .and_then(|attributes| attributes.get(name))
+                .cloned()
+        };
+
+        let timestamp = match (event.time_nano, event.time) {
+            (Some(time_nano), _) => time_nano / 1_000_000,
+            (None, Some(time)) => time * 1000,
+            (None, None) => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as i64,
+        };
+
+        let mut exit_code = attribute("exitCode").and_then(|code| code.parse().ok());
+        let mut oom_killed = None;
+
+        if action == "die" || action == "oom" {
+            let state = docker
+                .containers()
+                .get(&container_id)
+                .inspect()
+                .await
+                .ok()
+                .and_then(|inspection| inspection.state);
+
+            if let Some(state) = state {
+                exit_code = exit_code.or(state.exit_code.map(|code| code as i64));
+                oom_killed = state.oom_killed;
+            }
+        }
+
+        Some(Self {
+            container_id,
+            container_name: attribute("name").unwrap_or_default(),
+            image: attribute("image").unwrap_or_default(),
+            action,
+            exit_code,
+            oom_killed,
+            timestamp,
+        })
+    }
+}
@@ -1,16 +1,27 @@
 mod agent;
+mod buffer;
+mod config;
 mod container;
+mod encoding;
+mod filter;
+mod host;
+mod labels;
+mod lifecycle;
+mod metric;
+mod scrape;
+mod telemetry;
+
+use std::{net::SocketAddr, path::PathBuf};
 
 use anyhow::Result;
 use clap::Parser;
-use tracing::error;
 use tracing_subscriber::EnvFilter;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 
-use crate::agent::Agent;
+use crate::{agent::Agent, encoding::Compression};
 
-#[derive(Debug, Parser)]
+#[derive(Debug, Clone, Parser)]
 pub struct Opts {
     /// API URL.
     #[arg(
@@ -21,13 +32,181 @@ pub struct Opts {
     )]
     url: String,
 
-    /// API key.
-    #[arg(short = 'a', long = "apikey", env = "API_KEY")]
+    /// API key, required here or in the config file.
+    #[arg(
+        short = 'a',
+        long = "apikey",
+        env = "API_KEY",
+        default_value = "",
+        hide_default_value = true
+    )]
     apikey: String,
 
-    /// Regex to exclude containers.
+    /// Compression of the metrics sent to the API.
+    #[arg(
+        long = "compression",
+        env = "COMPRESSION",
+        value_enum,
+        default_value_t = Compression::Gzip
+    )]
+    compression: Compression,
+
+    /// Timeout of the requests to the API, in seconds.
+    #[arg(long = "api-timeout", env = "API_TIMEOUT", default_value_t = 30)]
+    api_timeout: u64,
+
+    /// Maximum size of a request body before compression, in bytes. Bigger batches are split in several requests.
+    #[arg(long = "max-body-bytes", env = "MAX_BODY_BYTES", default_value_t = 8 * 1024 * 1024)]
+    max_body_bytes: usize,
+
+    /// TOML or YAML file overriding the options, reloaded when it changes.
+    #[arg(short = 'c', long = "config", env = "CONFIG_FILE")]
+    config: Option<PathBuf>,
+
+    /// Regex to exclude containers by state.
     #[arg(short = 'e', long = "exclude", env = "EXCLUDE_CONTAINER_STATE")]
     exclude: Option<String>,
+
+    /// Regex the container name must match to be monitored.
+    #[arg(long = "include-name", env = "INCLUDE_CONTAINER_NAME")]
+    include_name: Option<String>,
+
+    /// Regex to exclude containers by name.
+    #[arg(long = "exclude-name", env = "EXCLUDE_CONTAINER_NAME")]
+    exclude_name: Option<String>,
+
+    /// Regex the container image must match to be monitored.
+    #[arg(long = "include-image", env = "INCLUDE_CONTAINER_IMAGE")]
+    include_image: Option<String>,
+
+    /// Regex to exclude containers by image.
+    #[arg(long = "exclude-image", env = "EXCLUDE_CONTAINER_IMAGE")]
+    exclude_image: Option<String>,
+
+    /// Docker label selectors (`key` or `key=value`) the container must all match to be monitored.
+    #[arg(
+        long = "include-label",
+        env = "INCLUDE_CONTAINER_LABELS",
+        value_delimiter = ','
+    )]
+    include_labels: Vec<String>,
+
+    /// Docker label selectors (`key` or `key=value`) excluding a container, for example `com.example.monitor=false`.
+    #[arg(
+        long = "exclude-label",
+        env = "EXCLUDE_CONTAINER_LABELS",
+        value_delimiter = ','
+    )]
+    exclude_labels: Vec<String>,
+
+    /// Docker endpoint, `unix:///path/to/docker.sock` or `tcp://host:port`.
+    /// Defaults to the system socket, or the rootless socket in `$XDG_RUNTIME_DIR`.
+    #[arg(long = "docker-host", env = "DOCKER_HOST")]
+    docker_host: Option<String>,
+
+    /// Directory with the `ca.pem`, `cert.pem` and `key.pem` files used for TLS over `tcp://`.
+    #[arg(long = "docker-cert-path", env = "DOCKER_CERT_PATH")]
+    docker_cert_path: Option<PathBuf>,
+
+    /// Use TLS and verify the docker daemon certificate (any value other than empty, `0` or `false`).
+    #[arg(long = "docker-tls-verify", env = "DOCKER_TLS_VERIFY")]
+    docker_tls_verify: Option<String>,
+
+    /// Name of the host in the `host` label, defaults to the name of the docker daemon host.
+    #[arg(long = "hostname", env = "AGENT_HOSTNAME")]
+    hostname: Option<String>,
+
+    /// Docker labels copied on the metrics of the container, `docker.label=metric_label` or `docker.label`.
+    #[arg(long = "label", env = "METRIC_LABELS", value_delimiter = ',')]
+    labels: Vec<String>,
+
+    /// Collect the metrics of the host (cpu, memory, load, disks, network and filesystems).
+    #[arg(long = "host-metrics", env = "HOST_METRICS")]
+    host_metrics: bool,
+
+    /// Root of procfs, use a bind mount of the host /proc (like /host/proc) when running in a container.
+    #[arg(long = "proc-root", env = "HOST_PROC", default_value = "/proc")]
+    proc_root: PathBuf,
+
+    /// Mount points where the filesystem usage is reported.
+    #[arg(long = "host-mount", env = "HOST_MOUNTS", value_delimiter = ',')]
+    host_mounts: Vec<PathBuf>,
+
+    /// Scrape the Prometheus endpoints of the containers with the `prometheus.io/scrape=true` and
+    /// `prometheus.io/port` labels (`prometheus.io/path`, `prometheus.io/scheme` and `prometheus.io/network` are optional).
+    #[arg(long = "scrape", env = "SCRAPE_CONTAINERS")]
+    scrape: bool,
+
+    /// Interval between two scrapes of the containers, in seconds.
+    #[arg(
+        long = "scrape-interval",
+        env = "SCRAPE_INTERVAL",
+        default_value_t = 15
+    )]
+    scrape_interval: u64,
+
+    /// Timeout of a scrape, in seconds.
+    #[arg(long = "scrape-timeout", env = "SCRAPE_TIMEOUT", default_value_t = 5)]
+    scrape_timeout: u64,
+
+    /// Pull the configuration managed in the web app from the API, it takes precedence over the config file.
+    #[arg(long = "central-config", env = "CENTRAL_CONFIG")]
+    central_config: bool,
+
+    /// Interval between two checks of the central configuration, in seconds.
+    #[arg(
+        long = "central-config-interval",
+        env = "CENTRAL_CONFIG_INTERVAL",
+        default_value_t = 60
+    )]
+    central_config_interval: u64,
+
+    /// File keeping the last central configuration, used at startup while the API is unreachable.
+    #[arg(long = "central-config-cache", env = "CENTRAL_CONFIG_CACHE")]
+    central_config_cache: Option<PathBuf>,
+
+    /// Address of the local HTTP listener serving `/healthz` and `/metrics` (disabled when unset).
+    #[arg(long = "listen", env = "AGENT_LISTEN")]
+    listen: Option<SocketAddr>,
+
+    /// Interval between two samples of the container stats, in seconds.
+    /// The stats stream of docker sends one sample every second, the others are skipped.
+    #[arg(long = "stats-interval", env = "STATS_INTERVAL", default_value_t = 1)]
+    stats_interval: u64,
+
+    /// Interval between two inspections of a container, in seconds.
+    /// Containers are also inspected on their docker events (start, die, rename, health status...).
+    #[arg(
+        long = "reinspect-interval",
+        env = "REINSPECT_INTERVAL",
+        default_value_t = 60
+    )]
+    reinspect_interval: u64,
+
+    /// Interval between two full refresh of the container list, in seconds.
+    /// Containers are followed with the docker events stream in between.
+    #[arg(
+        long = "resync-interval",
+        env = "RESYNC_INTERVAL",
+        default_value_t = 60
+    )]
+    resync_interval: u64,
+
+    /// Directory where batches are kept while the API is unreachable (disabled when unset).
+    #[arg(long = "buffer-dir", env = "BUFFER_DIR")]
+    buffer_dir: Option<PathBuf>,
+
+    /// Maximum size of the retry buffer on disk, in bytes.
+    #[arg(long = "buffer-max-bytes", env = "BUFFER_MAX_BYTES", default_value_t = 100 * 1024 * 1024)]
+    buffer_max_bytes: u64,
+
+    /// Maximum age of a buffered batch before it is dropped, in seconds.
+    #[arg(
+        long = "buffer-max-age",
+        env = "BUFFER_MAX_AGE",
+        default_value_t = 3600
+    )]
+    buffer_max_age: u64,
 }
 
 #[tokio::main]
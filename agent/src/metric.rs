@@ -0,0 +1,119 @@
+use std::{collections::HashMap, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+// Same format as the backend `VictoriaMetric`, one line of the VictoriaMetrics JSON line import.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VictoriaMetric {
+    pub metric: HashMap<String, String>,
+    pub values: Vec<f64>,
+    pub timestamps: Vec<i64>,
+}
+
+impl VictoriaMetric {
+    pub fn new(name: &str, value: f64, timestamp: i64) -> Self {
+        let mut metric = HashMap::new();
+        metric.insert("__name__".to_string(), name.to_string());
+
+        Self {
+            metric,
+            values: vec![value],
+            timestamps: vec![timestamp],
+        }
+    }
+
+    pub fn with_label(mut self, name: &str, value: &str) -> Self {
+        self.metric.insert(name.to_string(), value.to_string());
+        self
+    }
+}
+
+/// Increase of a counter between two samples.
+/// A counter lower than before was reset, by a container restart for example, and counts from zero.
+pub fn counter_delta(current: u64, previous: u64) -> u64 {
+    if current >= previous {
+        current - previous
+    } else {
+        current
+    }
+}
+
+/// Per-second rate of a counter between two samples taken `elapsed` apart.
+pub fn counter_rate(current: u64, previous: u64, elapsed: Duration) -> f64 {
+    if elapsed.is_zero() {
+        return 0.0;
+    }
+
+    counter_delta(current, previous) as f64 / elapsed.as_secs_f64()
+}
+
+/// Serialize a batch of metrics as JSON lines, the body expected by the backend `/insert` route.
+pub fn to_json_lines(metrics: &[VictoriaMetric]) -> Result<String, serde_json::Error> {
+    let mut body = String::new();
+
+    for metric in metrics {
+        body.push_str(&serde_json::to_string(metric)?);
+        body.push('\n');
+    }
+
+    Ok(body)
+}
+
+/// Serialize a batch of metrics as several bodies of JSON lines, each one smaller than `max_bytes`
+/// (unless a single metric is bigger).
+pub fn to_json_lines_chunks(
+    metrics: &[VictoriaMetric],
+    max_bytes: usize,
+) -> Result<Vec<String>, serde_json::Error> {
+    let mut bodies = Vec::new();
+    let mut body = String::new();
+
+    for metric in metrics {
+        let line = serde_json::to_string(metric)?;
+
+        if !body.is_empty() && body.len() + line.len() + 1 > max_bytes {
+            bodies.push(std::mem::take(&mut body));
+        }
+
+        body.push_str(&line);
+        body.push('\n');
+    }
+
+    if !body.is_empty() {
+        bodies.push(body);
+    }
+
+    Ok(bodies)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn counter_increase() {
+        assert_eq!(counter_delta(150, 100), 50);
+        assert_eq!(counter_delta(100, 100), 0);
+        assert_eq!(counter_rate(150, 100, Duration::from_secs(2)), 25.0);
+        assert_eq!(counter_rate(150, 100, Duration::from_millis(500)), 100.0);
+    }
+
+    #[test]
+    fn counter_reset() {
+        // The counter restarted from zero, everything it counted since is the increase.
+        assert_eq!(counter_delta(30, 100), 30);
+        assert_eq!(counter_rate(30, 100, Duration::from_secs(3)), 10.0);
+    }
+
+    #[test]
+    fn no_elapsed_time() {
+        assert_eq!(counter_rate(150, 100, Duration::ZERO), 0.0);
+
+        // Capture times in the wrong order give a zero duration, not a negative one.
+        let later = Instant::now();
+        let earlier = later - Duration::from_secs(1);
+        assert_eq!(counter_rate(150, 100, earlier - later), 0.0);
+    }
+}
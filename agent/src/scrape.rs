@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+
+use anyhow::{Result, anyhow};
+
+use crate::{container::Container, metric::VictoriaMetric};
+
+// Docker labels used to discover the scrape targets, like the Prometheus annotations in Kubernetes.
+const SCRAPE_LABEL: &str = "prometheus.io/scrape";
+const PORT_LABEL: &str = "prometheus.io/port";
+const PATH_LABEL: &str = "prometheus.io/path";
+const SCHEME_LABEL: &str = "prometheus.io/scheme";
+const NETWORK_LABEL: &str = "prometheus.io/network";
+
+/// Prometheus endpoint exposed by a container.
+#[derive(Debug, Clone)]
+pub struct ScrapeTarget {
+    pub url: String,
+}
+
+impl ScrapeTarget {
+    /// Find the scrape target of a container from its docker labels, `None` when the container is not scraped.
+    pub fn from_container(container: &Container) -> Option<Self> {
+        let labels = container.labels();
+
+        if labels.get(SCRAPE_LABEL).map(String::as_str) != Some("true") {
+            return None;
+        }
+
+        let port = labels.get(PORT_LABEL)?;
+        let path = labels
+            .get(PATH_LABEL)
+            .map(String::as_str)
+            .unwrap_or("/metrics");
+        let scheme = labels
+            .get(SCHEME_LABEL)
+            .map(String::as_str)
+            .unwrap_or("http");
+        let ip_address = container.ip_address(labels.get(NETWORK_LABEL).map(String::as_str))?;
+
+        Some(Self {
+            url: format!(
+                "{}://{}:{}/{}",
+                scheme,
+                ip_address,
+                port,
+                path.trim_start_matches('/')
+            ),
+        })
+    }
+}
+
+/// Parse the Prometheus text exposition format.
+///
+/// Samples without timestamp get `default_timestamp`, in milliseconds.
+/// NaN and infinite values are skipped, they can't be sent in JSON.
+pub fn parse_exposition(text: &str, default_timestamp: i64) -> Result<Vec<VictoriaMetric>> {
+    let mut metrics = Vec::new();
+
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (name, labels, rest) = parse_series(line)?;
+
+        let mut parts = rest.split_whitespace();
+        let value = parts
+            .next()
+            .ok_or_else(|| anyhow!("Missing value in \"{}\"", line))?;
+        let value = parse_value(value)?;
+        if !value.is_finite() {
+            continue;
+        }
+
+        let timestamp = match parts.next() {
+            Some(timestamp) => timestamp.parse::<i64>()?,
+            None => default_timestamp,
+        };
+
+        let mut metric = VictoriaMetric::new(name, value, timestamp);
+        for (label, label_value) in labels {
+            metric = metric.with_label(&label, &label_value);
+        }
+        metrics.push(metric);
+    }
+
+    Ok(metrics)
+}
+
+// Split `name{label="value",...} rest` in its name, labels and the rest of the line.
+fn parse_series(line: &str) -> Result<(&str, HashMap<String, String>, &str)> {
+    let name_end = line
+        .find(|c: char| c == '{' || c.is_whitespace())
+        .ok_or_else(|| anyhow!("Missing value in \"{}\"", line))?;
+    let name = &line[..name_end];
+    let mut labels = HashMap::new();
+
+    if !line[name_end..].starts_with('{') {
+        return Ok((name, labels, &line[name_end..]));
+    }
+
+    let mut label = String::new();
+    let mut value = String::new();
+    let mut in_value = false;
+    let mut escaped = false;
+
+    for (index, c) in line[name_end + 1..].char_indices() {
+        if in_value {
+            match (escaped, c) {
+                (true, 'n') => {
+                    value.push('\n');
+                    escaped = false;
+                }
+                (true, c) => {
+                    value.push(c);
+                    escaped = false;
+                }
+                (false, '\\') => escaped = true,
+                (false, '"') => {
+                    labels.insert(
+                        std::mem::take(&mut label).trim().to_string(),
+                        std::mem::take(&mut value),
+                    );
+                    in_value = false;
+                }
+                (false, c) => value.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            '}' => return Ok((name, labels, &line[name_end + 1 + index + 1..])),
+            '"' => in_value = true,
+            '=' | ',' => {}
+            c if c.is_whitespace() => {}
+            c => label.push(c),
+        }
+    }
+
+    Err(anyhow!("Unclosed labels in \"{}\"", line))
+}
+
+fn parse_value(value: &str) -> Result<f64> {
+    match value {
+        "+Inf" | "Inf" => Ok(f64::INFINITY),
+        "-Inf" => Ok(f64::NEG_INFINITY),
+        "NaN" => Ok(f64::NAN),
+        value => Ok(value.parse::<f64>()?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000_000;
+
+    #[test]
+    fn help_and_type_lines_are_skipped() {
+        let text = "\
+# HELP http_requests_total Total of the HTTP requests.
+# TYPE http_requests_total counter
+http_requests_total{method=\"get\",code=\"200\"} 1027
+http_requests_total{method=\"post\",code=\"400\"} 3
+
+# HELP process_open_fds Open file descriptors.
+# TYPE process_open_fds gauge
+process_open_fds 12
+";
+
+        let metrics = parse_exposition(text, NOW).unwrap();
+
+        assert_eq!(metrics.len(), 3);
+        assert_eq!(metrics[0].metric["__name__"], "http_requests_total");
+        assert_eq!(metrics[0].metric["method"], "get");
+        assert_eq!(metrics[0].metric["code"], "200");
+        assert_eq!(metrics[0].values, [1027.0]);
+        assert_eq!(metrics[0].timestamps, [NOW]);
+        assert_eq!(metrics[2].metric["__name__"], "process_open_fds");
+        assert_eq!(metrics[2].metric.len(), 1);
+    }
+
+    #[test]
+    fn escaped_label_values() {
+        let text =
+            r#"log_lines{path="C:\\logs",message="say \"hi\"\nbye",list="a,b=c",braces="{}"} 1"#;
+
+        let metrics = parse_exposition(text, NOW).unwrap();
+
+        let labels = &metrics[0].metric;
+        assert_eq!(labels["path"], r"C:\logs");
+        assert_eq!(labels["message"], "say \"hi\"\nbye");
+        assert_eq!(labels["list"], "a,b=c");
+        assert_eq!(labels["braces"], "{}");
+    }
+
+    #[test]
+    fn timestamps_and_values() {
+        let text = "\
+temperature{room=\"kitchen\", } 21.5 1600000000000
+ratio 1e-3
+infinite +Inf
+missing NaN
+negative -2
+";
+
+        let metrics = parse_exposition(text, NOW).unwrap();
+
+        // NaN and infinite values are skipped.
+        assert_eq!(metrics.len(), 3);
+        assert_eq!(metrics[0].metric["room"], "kitchen");
+        assert_eq!(metrics[0].timestamps, [1_600_000_000_000]);
+        assert_eq!(metrics[1].values, [0.001]);
+        assert_eq!(metrics[1].timestamps, [NOW]);
+        assert_eq!(metrics[2].values, [-2.0]);
+    }
+
+    #[test]
+    fn empty_exposition() {
+        assert!(
+            parse_exposition("# no samples\n\n", NOW)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn invalid_lines() {
+        for line in [
+            "no_value",
+            "no_value{a=\"b\"}",
+            "unclosed{a=\"b\" 1",
+            "bad_value abc",
+            "bad_timestamp 1 now",
+        ] {
+            assert!(parse_exposition(line, NOW).is_err(), "line {:?}", line);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicI64, AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use axum::{Router, extract::State, http::StatusCode, response::IntoResponse, routing::get};
+use tracing::info;
+
+use crate::agent::Agent;
+
+// The agent is unhealthy when uploads have been failing for longer than this.
+const UPLOAD_FAILURE_GRACE: Duration = Duration::from_secs(60);
+
+/// Counters about the agent itself, exposed on the local `/metrics` endpoint.
+#[derive(Debug)]
+pub struct Telemetry {
+    upload_success: AtomicU64,
+    upload_failure: AtomicU64,
+    // Unix timestamps in milliseconds, the last success starts at the agent start.
+    last_upload_success: AtomicI64,
+    last_upload_failure: AtomicI64,
+
+    stats_query_count: AtomicU64,
+    stats_query_micros: AtomicU64,
+}
+
+/// Values of the agent read when rendering the metrics.
+#[derive(Debug)]
+pub struct AgentState {
+    pub containers: usize,
+    pub container_processors: usize,
+    pub buffered_samples: u64,
+    pub dropped_samples: u64,
+    pub config_revision: i64,
+}
+
+impl Telemetry {
+    pub fn new() -> Self {
+        Self {
+            upload_success: AtomicU64::new(0),
+            upload_failure: AtomicU64::new(0),
+            last_upload_success: AtomicI64::new(now_millis()),
+            last_upload_failure: AtomicI64::new(0),
+
+            stats_query_count: AtomicU64::new(0),
+            stats_query_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record_upload(&self, success: bool) {
+        if success {
+            self.upload_success.fetch_add(1, Ordering::Relaxed);
+            self.last_upload_success
+                .store(now_millis(), Ordering::Relaxed);
+        } else {
+            self.upload_failure.fetch_add(1, Ordering::Relaxed);
+            self.last_upload_failure
+                .store(now_millis(), Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_stats_query(&self, duration: Duration) {
+        self.stats_query_count.fetch_add(1, Ordering::Relaxed);
+        self.stats_query_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        let last_success = self.last_upload_success.load(Ordering::Relaxed);
+        let last_failure = self.last_upload_failure.load(Ordering::Relaxed);
+
+        last_failure <= last_success
+            || now_millis() - last_success < UPLOAD_FAILURE_GRACE.as_millis() as i64
+    }
+
+    /// Render the metrics in the Prometheus text format.
+    pub fn render(&self, state: &AgentState) -> String {
+        let mut body = String::new();
+
+        let gauges = [
+            (
+                "agent_containers",
+                "Number of containers tracked by the agent.",
+                state.containers as f64,
+            ),
+            (
+                "agent_container_processors",
+                "Number of running container processors.",
+                state.container_processors as f64,
+            ),
+            (
+                "agent_last_upload_success_timestamp_seconds",
+                "Unix time of the last successful upload.",
+                self.last_upload_success.load(Ordering::Relaxed) as f64 / 1000.0,
+            ),
+            (
+                "agent_buffer_samples",
+                "Samples waiting in the retry buffer.",
+                state.buffered_samples as f64,
+            ),
+            (
+                "agent_buffer_dropped_samples",
+                "Samples dropped since the agent started.",
+                state.dropped_samples as f64,
+            ),
+            (
+                "agent_config_revision",
+                "Revision of the central configuration applied by the agent.",
+                state.config_revision as f64,
+            ),
+        ];
+
+        for (name, help, value) in gauges {
+            let _ = writeln!(body, "# HELP {} {}", name, help);
+            let _ = writeln!(body, "# TYPE {} gauge", name);
+            let _ = writeln!(body, "{} {}", name, value);
+        }
+
+        let _ = writeln!(
+            body,
+            "# HELP agent_uploads_total Uploads to the API by result."
+        );
+        let _ = writeln!(body, "# TYPE agent_uploads_total counter");
+        let _ = writeln!(
+            body,
+            "agent_uploads_total{{result=\"success\"}} {}",
+            self.upload_success.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            body,
+            "agent_uploads_total{{result=\"failure\"}} {}",
+            self.upload_failure.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            body,
+            "# HELP agent_stats_query_duration_seconds Duration of the docker stats queries."
+        );
+        let _ = writeln!(body, "# TYPE agent_stats_query_duration_seconds summary");
+        let _ = writeln!(
+            body,
+            "agent_stats_query_duration_seconds_sum {}",
+            self.stats_query_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0
+        );
+        let _ = writeln!(
+            body,
+            "agent_stats_query_duration_seconds_count {}",
+            self.stats_query_count.load(Ordering::Relaxed)
+        );
+
+        body
+    }
+}
+
+/// Serve `/healthz` and `/metrics` on the given address.
+pub async fn serve(agent: Arc<Agent>, address: SocketAddr) -> Result<()> {
+    let app = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/metrics", get(metrics))
+        .with_state(agent);
+
+    info!("Telemetry listening on {}", address);
+    let listener = tokio::net::TcpListener::bind(address).await?;
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
+
+async fn healthz(State(agent): State<Arc<Agent>>) -> impl IntoResponse {
+    if agent.telemetry().is_healthy() {
+        (StatusCode::OK, "ok")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "uploads are failing")
+    }
+}
+
+async fn metrics(State(agent): State<Arc<Agent>>) -> impl IntoResponse {
+    let state = agent.telemetry_state().await;
+
+    (
+        [("Content-Type", "text/plain; version=0.0.4")],
+        agent.telemetry().render(&state),
+    )
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
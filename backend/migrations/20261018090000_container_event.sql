@@ -0,0 +1,20 @@
+-- Container lifecycle events (start, die, oom...) sent by the agents.
+create table if not exists container_event
+(
+    id uuid DEFAULT uuidv7() primary key,
+    id_company uuid not null,
+    id_agent uuid not null,
+    container_id text not null,
+    container_name text not null,
+    image text not null,
+    action text not null,
+    exit_code bigint,
+    oom_killed boolean,
+    -- unix timestamp in milliseconds, like VictoriaMetrics samples.
+    timestamp bigint not null,
+    FOREIGN KEY (id_company) REFERENCES company(id),
+    FOREIGN KEY (id_agent) REFERENCES agent(id) ON DELETE CASCADE
+);
+
+create index if not exists container_event_company_timestamp
+    on container_event (id_company, timestamp);
@@ -0,0 +1,10 @@
+-- Configuration of an agent managed from the web app, pulled by the agent.
+create table if not exists agent_config
+(
+    id_agent uuid primary key,
+    -- increased on every change, the agent reports the revision it runs.
+    revision bigint not null default 1,
+    config jsonb not null,
+    applied_revision bigint,
+    FOREIGN KEY (id_agent) REFERENCES agent(id) ON DELETE CASCADE
+);
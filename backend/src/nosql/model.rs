@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use axum::{
     Json,
-    http::StatusCode,
+    http::{StatusCode, header},
     response::{IntoResponse, Response},
 };
 use axum_login::tracing::debug;
@@ -20,6 +20,84 @@ pub enum AppError {
     EmptyArgument,
     #[error("trying to create an element already present")]
     AlreadyUsed,
+    #[error("invalid payload: {0}")]
+    InvalidPayload(String),
+    #[error("payload too large")]
+    PayloadTooLarge,
+    #[error("unsupported content encoding: {0}")]
+    UnsupportedEncoding(String),
+    #[error("unsupported content type: {0}")]
+    UnsupportedContentType(String),
+    #[error("storage unavailable: {0}")]
+    UpstreamUnavailable(String),
+    #[error("storage failed: {0}")]
+    UpstreamFailed(String),
+    #[error("rejected by storage: {0}")]
+    UpstreamRejected(String),
+    #[error("invalid samples")]
+    InvalidSamples(IngestReport),
+    #[error("internal error: {0}")]
+    Internal(String),
+}
+
+// Seconds the agents should wait before sending again samples that the storage could not take.
+const UPSTREAM_RETRY_AFTER_SECONDS: u64 = 5;
+
+/// Body of the errors of the storage, so the agents know if the samples can be sent again.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IngestError {
+    pub error: String,
+    pub message: String,
+    pub retryable: bool,
+    #[serde(flatten, default, skip_serializing_if = "Option::is_none")]
+    pub report: Option<IngestReport>,
+}
+
+/// Samples stored and samples refused by the validation of an ingestion request.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct IngestReport {
+    pub accepted_samples: usize,
+    pub rejected_samples: usize,
+    pub rejected: Vec<SampleRejection>,
+}
+
+impl IngestReport {
+    /// `success` when every sample was stored, the report with a 200 otherwise.
+    pub fn into_response(self, success: StatusCode) -> Response {
+        if self.rejected_samples == 0 {
+            success.into_response()
+        } else {
+            (StatusCode::OK, Json(self)).into_response()
+        }
+    }
+}
+
+/// Why a sample was not stored. `series` is the index of the series in the request,
+/// `sample` the index of the sample in the series, or `None` when the whole series is invalid.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SampleRejection {
+    pub series: usize,
+    pub sample: Option<usize>,
+    pub metric: String,
+    pub reason: String,
+}
+
+impl IngestError {
+    fn into_response(self, status: StatusCode) -> Response {
+        if self.retryable {
+            (
+                status,
+                [(
+                    header::RETRY_AFTER,
+                    UPSTREAM_RETRY_AFTER_SECONDS.to_string(),
+                )],
+                Json(self),
+            )
+                .into_response()
+        } else {
+            (status, Json(self)).into_response()
+        }
+    }
 }
 
 impl IntoResponse for AppError {
@@ -37,6 +115,57 @@ impl IntoResponse for AppError {
                 Json("trying to create an element already present"),
             )
                 .into_response(),
+            AppError::InvalidPayload(reason) => (
+                StatusCode::BAD_REQUEST,
+                Json(format!("Invalid payload: {}", reason)),
+            )
+                .into_response(),
+            AppError::PayloadTooLarge => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Json("Payload too large once decompressed"),
+            )
+                .into_response(),
+            AppError::UnsupportedEncoding(encoding) => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                Json(format!("Unsupported content encoding: {}", encoding)),
+            )
+                .into_response(),
+            AppError::UnsupportedContentType(content_type) => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                Json(format!("Unsupported content type: {}", content_type)),
+            )
+                .into_response(),
+            AppError::UpstreamUnavailable(message) => IngestError {
+                error: "storage_unavailable".to_string(),
+                message,
+                retryable: true,
+                report: None,
+            }
+            .into_response(StatusCode::SERVICE_UNAVAILABLE),
+            AppError::UpstreamFailed(message) => IngestError {
+                error: "storage_failed".to_string(),
+                message,
+                retryable: true,
+                report: None,
+            }
+            .into_response(StatusCode::BAD_GATEWAY),
+            AppError::UpstreamRejected(message) => IngestError {
+                error: "rejected".to_string(),
+                message,
+                retryable: false,
+                report: None,
+            }
+            .into_response(StatusCode::BAD_REQUEST),
+            AppError::InvalidSamples(report) => IngestError {
+                error: "invalid_samples".to_string(),
+                message: format!("{} samples rejected", report.rejected_samples),
+                retryable: false,
+                report: Some(report),
+            }
+            .into_response(StatusCode::BAD_REQUEST),
+            AppError::Internal(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, "Internal error").into_response()
+            }
         }
     }
 }
@@ -81,6 +210,72 @@ pub struct PubAgent {
     pub name: String,
     pub token: String,
 }
+// Container lifecycle event sent by an agent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewContainerEvent {
+    pub container_id: String,
+    pub container_name: String,
+    pub image: String,
+    pub action: String,
+    pub exit_code: Option<i64>,
+    pub oom_killed: Option<bool>,
+    pub timestamp: i64,
+}
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ContainerEvent {
+    pub id: Uuid,
+    pub agent_name: String,
+    pub container_id: String,
+    pub container_name: String,
+    pub image: String,
+    pub action: String,
+    pub exit_code: Option<i64>,
+    pub oom_killed: Option<bool>,
+    pub timestamp: i64,
+}
+// Settings of an agent managed from the web app, same keys as the agent config file.
+// Unset values keep the value configured on the agent host.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AgentSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude_image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_labels: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude_labels: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labels: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host_metrics: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scrape: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats_interval: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reinspect_interval: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scrape_interval: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scrape_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resync_interval: Option<u64>,
+}
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct AgentConfig {
+    pub revision: i64,
+    // last revision reported by the agent, none until it pulled the config once.
+    pub applied_revision: Option<i64>,
+    pub config: sqlx::types::Json<AgentSettings>,
+}
 // ex : {"metric":{"__name__":"evan-metric1","job":"curl","instance":"vmagent:8429"},"values":[100,300],"timestamps":[1763074402660,1763074402661]}
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VictoriaMetric {
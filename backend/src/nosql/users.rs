@@ -38,7 +38,7 @@ pub struct Backend {
 
 impl Backend {
     pub fn new(db: PgPool) -> Self {
-        Self { db: db }
+        Self { db }
     }
 }
 
@@ -0,0 +1,441 @@
+use std::time::Duration;
+
+use clap::ValueEnum;
+
+use super::model::{IngestReport, SampleRejection, VictoriaMetric};
+
+// Rejections listed in the answer, the others are only counted so the body stays small when a whole batch is invalid.
+const MAX_REPORTED_REJECTIONS: usize = 100;
+
+// Labels set by the agents or by the API on every series they send, a label mapping of an agent can't replace them.
+// The agent checks its mappings against the same list.
+const RESERVED_LABELS: [&str; 10] = [
+    "host",
+    "container_id",
+    "container_name",
+    "image",
+    "job",
+    "exported_job",
+    "interface",
+    "device",
+    "mountpoint",
+    "state",
+];
+const RESERVED_LABEL_PREFIX: &str = "compose_";
+
+/// What happens to a request with invalid samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ValidationMode {
+    /// Store the valid samples and report the others.
+    Drop,
+    /// Refuse the whole request.
+    Reject,
+}
+
+/// Rules checked on every sample before it is sent to VictoriaMetrics.
+#[derive(Debug, Clone)]
+pub struct ValidationRules {
+    pub mode: ValidationMode,
+    pub max_age: Duration,
+    pub max_future: Duration,
+    pub max_labels: usize,
+    pub max_label_value_length: usize,
+}
+
+impl ValidationRules {
+    /// Split the metrics between the valid samples and the report of the invalid ones.
+    /// `now` is in milliseconds, like the timestamps of the samples.
+    pub fn validate(
+        &self,
+        metrics: Vec<VictoriaMetric>,
+        now: i64,
+    ) -> (Vec<VictoriaMetric>, IngestReport) {
+        // Saturating, a huge bound accepts every timestamp instead of overflowing.
+        let oldest = now.saturating_sub(millis(self.max_age));
+        let newest = now.saturating_add(millis(self.max_future));
+
+        let mut valid = Vec::with_capacity(metrics.len());
+        let mut report = IngestReport::default();
+
+        for (series, mut metric) in metrics.into_iter().enumerate() {
+            let name = metric.metric.get("__name__").cloned().unwrap_or_default();
+
+            if let Err(reason) = self.check_series(&metric) {
+                let samples = metric.values.len().max(metric.timestamps.len()).max(1);
+                let rejection = SampleRejection {
+                    series,
+                    sample: None,
+                    metric: name,
+                    reason,
+                };
+                reject(&mut report, rejection, samples);
+                continue;
+            }
+
+            let mut values = Vec::with_capacity(metric.values.len());
+            let mut timestamps = Vec::with_capacity(metric.timestamps.len());
+            for (sample, (value, timestamp)) in metric
+                .values
+                .iter()
+                .zip(metric.timestamps.iter())
+                .enumerate()
+            {
+                let reason = if !value.is_finite() {
+                    Some("value is NaN or infinite")
+                } else if *timestamp < oldest {
+                    Some("timestamp is older than the accepted age")
+                } else if *timestamp > newest {
+                    Some("timestamp is too far in the future")
+                } else {
+                    None
+                };
+
+                match reason {
+                    Some(reason) => {
+                        let rejection = SampleRejection {
+                            series,
+                            sample: Some(sample),
+                            metric: name.clone(),
+                            reason: reason.to_string(),
+                        };
+                        reject(&mut report, rejection, 1);
+                    }
+                    None => {
+                        values.push(*value);
+                        timestamps.push(*timestamp);
+                    }
+                }
+            }
+
+            if values.is_empty() {
+                continue;
+            }
+            report.accepted_samples += values.len();
+            metric.values = values;
+            metric.timestamps = timestamps;
+            valid.push(metric);
+        }
+
+        (valid, report)
+    }
+
+    // Problems making every sample of the series invalid.
+    fn check_series(&self, metric: &VictoriaMetric) -> Result<(), String> {
+        if metric.values.len() != metric.timestamps.len() {
+            return Err(format!(
+                "{} values for {} timestamps",
+                metric.values.len(),
+                metric.timestamps.len()
+            ));
+        }
+        if metric.values.is_empty() {
+            return Err("no samples".to_string());
+        }
+
+        match metric.metric.get("__name__") {
+            None => return Err("missing metric name".to_string()),
+            Some(name) if name.is_empty() => return Err("empty metric name".to_string()),
+            Some(name) if !is_valid_name(name, true) => {
+                return Err(format!("invalid metric name \"{}\"", name));
+            }
+            _ => {}
+        }
+
+        if metric.metric.len() > self.max_labels {
+            return Err(format!(
+                "{} labels, at most {} are accepted",
+                metric.metric.len(),
+                self.max_labels
+            ));
+        }
+
+        for (label, value) in &metric.metric {
+            // Names starting with __ are reserved to VictoriaMetrics.
+            if label != "__name__" && !is_valid_label_name(label) {
+                return Err(format!("invalid label name \"{}\"", label));
+            }
+            if value.chars().count() > self.max_label_value_length {
+                return Err(format!(
+                    "value of label \"{}\" is longer than {} characters",
+                    label, self.max_label_value_length
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn millis(duration: Duration) -> i64 {
+    i64::try_from(duration.as_millis()).unwrap_or(i64::MAX)
+}
+
+// Count a rejection, and list it while the report is small.
+fn reject(report: &mut IngestReport, rejection: SampleRejection, samples: usize) {
+    report.rejected_samples += samples;
+    if report.rejected.len() < MAX_REPORTED_REJECTIONS {
+        report.rejected.push(rejection);
+    }
+}
+
+/// Replace the characters not allowed in Prometheus metric and label names by `_`, for the formats
+/// where they are common (`service.name` becomes `service_name`). Metric names keep their `:`.
+pub fn sanitize_name(name: &str, metric_name: bool) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || (metric_name && c == ':') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    if sanitized.starts_with(|c: char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+
+    sanitized
+}
+
+/// Whether a label name is accepted: Prometheus charset, names starting with `__` are reserved to VictoriaMetrics.
+pub fn is_valid_label_name(name: &str) -> bool {
+    !name.starts_with("__") && is_valid_name(name, false)
+}
+
+/// Whether a label is set by the agents or the API, so it can't come from a label mapping.
+pub fn is_reserved_label(name: &str) -> bool {
+    RESERVED_LABELS.contains(&name) || name.starts_with(RESERVED_LABEL_PREFIX)
+}
+
+// Prometheus charset: letters, digits and `_`, not starting with a digit. Metric names can also have `:`.
+fn is_valid_name(name: &str, metric_name: bool) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || (metric_name && c == ':'))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000_000;
+    const HOUR: i64 = 3_600_000;
+
+    fn rules(mode: ValidationMode) -> ValidationRules {
+        ValidationRules {
+            mode,
+            max_age: Duration::from_secs(24 * 3600),
+            max_future: Duration::from_secs(3600),
+            max_labels: 4,
+            max_label_value_length: 20,
+        }
+    }
+
+    fn metric(labels: &[(&str, &str)], values: &[f64], timestamps: &[i64]) -> VictoriaMetric {
+        VictoriaMetric {
+            metric: labels
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<HashMap<_, _>>(),
+            values: values.to_vec(),
+            timestamps: timestamps.to_vec(),
+        }
+    }
+
+    fn reasons(report: &IngestReport) -> Vec<(usize, Option<usize>, &str)> {
+        report
+            .rejected
+            .iter()
+            .map(|rejection| {
+                (
+                    rejection.series,
+                    rejection.sample,
+                    rejection.reason.as_str(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn valid_samples_are_kept() {
+        let metrics = vec![metric(
+            &[("__name__", "http:requests_total"), ("code", "200")],
+            &[1.0, 2.0],
+            &[NOW - HOUR, NOW],
+        )];
+
+        let (valid, report) = rules(ValidationMode::Drop).validate(metrics, NOW);
+
+        assert_eq!(valid.len(), 1);
+        assert_eq!(valid[0].values, [1.0, 2.0]);
+        assert_eq!(report.accepted_samples, 2);
+        assert_eq!(report.rejected_samples, 0);
+    }
+
+    #[test]
+    fn length_mismatch_rejects_the_series() {
+        let metrics = vec![metric(&[("__name__", "up")], &[1.0, 2.0, 3.0], &[NOW])];
+
+        let (valid, report) = rules(ValidationMode::Drop).validate(metrics, NOW);
+
+        assert!(valid.is_empty());
+        assert_eq!(report.rejected_samples, 3);
+        assert_eq!(reasons(&report), [(0, None, "3 values for 1 timestamps")]);
+    }
+
+    #[test]
+    fn non_finite_values_are_dropped() {
+        let metrics = vec![metric(
+            &[("__name__", "up")],
+            &[f64::NAN, 1.0, f64::INFINITY, f64::NEG_INFINITY],
+            &[NOW, NOW, NOW, NOW],
+        )];
+
+        let (valid, report) = rules(ValidationMode::Drop).validate(metrics, NOW);
+
+        assert_eq!(valid[0].values, [1.0]);
+        assert_eq!(report.accepted_samples, 1);
+        assert_eq!(report.rejected_samples, 3);
+        assert_eq!(report.rejected[0].sample, Some(0));
+        assert_eq!(report.rejected[0].reason, "value is NaN or infinite");
+    }
+
+    #[test]
+    fn age_and_future_bounds() {
+        let metrics = vec![metric(
+            &[("__name__", "up")],
+            &[1.0, 2.0, 3.0, 4.0],
+            &[NOW - 25 * HOUR, NOW - 24 * HOUR, NOW + HOUR, NOW + 2 * HOUR],
+        )];
+
+        let (valid, report) = rules(ValidationMode::Drop).validate(metrics, NOW);
+
+        assert_eq!(valid[0].values, [2.0, 3.0]);
+        assert_eq!(
+            reasons(&report),
+            [
+                (0, Some(0), "timestamp is older than the accepted age"),
+                (0, Some(3), "timestamp is too far in the future"),
+            ]
+        );
+    }
+
+    #[test]
+    fn huge_bounds_do_not_overflow() {
+        let mut rules = rules(ValidationMode::Drop);
+        rules.max_age = Duration::MAX;
+        rules.max_future = Duration::MAX;
+        let metrics = vec![metric(&[("__name__", "up")], &[1.0, 2.0], &[0, i64::MAX])];
+
+        let (valid, report) = rules.validate(metrics, NOW);
+
+        assert_eq!(valid[0].values, [1.0, 2.0]);
+        assert_eq!(report.rejected_samples, 0);
+    }
+
+    #[test]
+    fn invalid_names_and_labels() {
+        let metrics = vec![
+            metric(&[("job", "a")], &[1.0], &[NOW]),
+            metric(&[("__name__", "1up")], &[1.0], &[NOW]),
+            metric(&[("__name__", "up"), ("__tenant", "a")], &[1.0], &[NOW]),
+            metric(&[("__name__", "up"), ("a:b", "a")], &[1.0], &[NOW]),
+            metric(
+                &[("__name__", "up"), ("path", "/a/very/long/path/to/x")],
+                &[1.0],
+                &[NOW],
+            ),
+            metric(
+                &[
+                    ("__name__", "up"),
+                    ("a", ""),
+                    ("b", ""),
+                    ("c", ""),
+                    ("d", ""),
+                ],
+                &[1.0],
+                &[NOW],
+            ),
+        ];
+
+        let (valid, report) = rules(ValidationMode::Drop).validate(metrics, NOW);
+
+        assert!(valid.is_empty());
+        assert_eq!(
+            reasons(&report),
+            [
+                (0, None, "missing metric name"),
+                (1, None, "invalid metric name \"1up\""),
+                (2, None, "invalid label name \"__tenant\""),
+                (3, None, "invalid label name \"a:b\""),
+                (
+                    4,
+                    None,
+                    "value of label \"path\" is longer than 20 characters"
+                ),
+                (5, None, "5 labels, at most 4 are accepted"),
+            ]
+        );
+    }
+
+    #[test]
+    fn drop_and_reject_modes_report_the_same_samples() {
+        // The mode is applied by the import, the validation always keeps the valid samples.
+        let metrics = || {
+            vec![
+                metric(&[("__name__", "up")], &[1.0], &[NOW]),
+                metric(&[("__name__", "up")], &[f64::NAN], &[NOW]),
+            ]
+        };
+
+        for mode in [ValidationMode::Drop, ValidationMode::Reject] {
+            let (valid, report) = rules(mode).validate(metrics(), NOW);
+            assert_eq!(valid.len(), 1);
+            assert_eq!(report.accepted_samples, 1);
+            assert_eq!(report.rejected_samples, 1);
+        }
+    }
+
+    #[test]
+    fn report_is_bounded() {
+        let metrics = (0..MAX_REPORTED_REJECTIONS + 10)
+            .map(|_| metric(&[("__name__", "up")], &[f64::NAN], &[NOW]))
+            .collect();
+
+        let (_, report) = rules(ValidationMode::Drop).validate(metrics, NOW);
+
+        assert_eq!(report.rejected_samples, MAX_REPORTED_REJECTIONS + 10);
+        assert_eq!(report.rejected.len(), MAX_REPORTED_REJECTIONS);
+    }
+
+    #[test]
+    fn label_names() {
+        assert!(is_valid_label_name("team"));
+        assert!(is_valid_label_name("_team"));
+        assert!(!is_valid_label_name("__team"));
+        assert!(!is_valid_label_name("1team"));
+        assert!(!is_valid_label_name("a:b"));
+
+        assert!(is_reserved_label("host"));
+        assert!(is_reserved_label("state"));
+        assert!(is_reserved_label("compose_service"));
+        assert!(!is_reserved_label("hostname"));
+    }
+
+    #[test]
+    fn sanitized_names() {
+        assert_eq!(sanitize_name("service.name", false), "service_name");
+        assert_eq!(sanitize_name("http:requests", false), "http_requests");
+        assert_eq!(
+            sanitize_name("http:requests.total", true),
+            "http:requests_total"
+        );
+        assert_eq!(sanitize_name("2xx", true), "_2xx");
+    }
+}
@@ -2,14 +2,20 @@ pub use app::App;
 mod app;
 mod extractor {
     pub mod current_user;
+    pub mod decoded_body;
 }
 mod middleware {
     pub mod agent_token_validation;
 }
 
 mod controller {
+    pub mod agent_config;
     pub mod auth;
+    pub mod events;
+    pub mod influx;
+    pub mod otlp;
     pub mod protected;
     pub mod public;
+    pub mod remote_write;
     pub mod victoria_api;
 }
@@ -0,0 +1,283 @@
+use axum::{
+    Extension, Json, Router,
+    extract::{self, Path, State},
+    http::{HeaderMap, StatusCode},
+    routing::get,
+};
+use regex::Regex;
+use sqlx::Pool as sqlxPool;
+
+use super::super::super::{
+    model::{Agent, AgentConfig, AgentSettings, AppError},
+    validation::{is_reserved_label, is_valid_label_name},
+    web::{App, extractor::current_user::CurrentUser},
+};
+
+// Header where the agent sends the revision it is running.
+const REVISION_HEADER: &str = "X-Config-Revision";
+
+// Routes used by the agents, they must be behind the agent token validation.
+pub fn agent_router() -> Router<App> {
+    Router::new().route("/agent-config", get(self::get::agent_config))
+}
+
+pub fn router() -> Router<App> {
+    Router::new().route(
+        "/agent/{name}/config",
+        get(self::get::config).put(self::put::config),
+    )
+}
+
+/// Check the settings with the rules of the agent, so a configuration it would refuse is never stored.
+fn validate_settings(settings: &AgentSettings) -> Result<(), String> {
+    for (option, pattern) in [
+        ("exclude", &settings.exclude),
+        ("include_name", &settings.include_name),
+        ("exclude_name", &settings.exclude_name),
+        ("include_image", &settings.include_image),
+        ("exclude_image", &settings.exclude_image),
+    ] {
+        if let Some(pattern) = pattern
+            && let Err(e) = Regex::new(pattern)
+        {
+            return Err(format!("invalid {} regex \"{}\": {}", option, pattern, e));
+        }
+    }
+
+    // Docker label selectors, `key` or `key=value`.
+    for selector in settings
+        .include_labels
+        .iter()
+        .chain(&settings.exclude_labels)
+        .flatten()
+    {
+        let key = selector
+            .split_once('=')
+            .map_or(selector.as_str(), |(key, _)| key);
+        if key.trim().is_empty() {
+            return Err(format!("invalid label selector \"{}\"", selector));
+        }
+    }
+
+    // Label mappings, `docker.label=metric_label` or `docker.label`.
+    for mapping in settings.labels.iter().flatten() {
+        let (docker_label, metric_label) = match mapping.split_once('=') {
+            Some((docker_label, metric_label)) => {
+                (docker_label.trim(), metric_label.trim().to_string())
+            }
+            None => (
+                mapping.trim(),
+                mapping
+                    .trim()
+                    .chars()
+                    .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                    .collect(),
+            ),
+        };
+        if docker_label.is_empty() || !is_valid_label_name(&metric_label) {
+            return Err(format!("invalid label mapping \"{}\"", mapping));
+        }
+        if is_reserved_label(&metric_label) {
+            return Err(format!(
+                "invalid label mapping \"{}\", the label \"{}\" is set by the agent",
+                mapping, metric_label
+            ));
+        }
+    }
+
+    // A zero interval would make the agent loop without pause.
+    for (option, interval) in [
+        ("stats_interval", settings.stats_interval),
+        ("reinspect_interval", settings.reinspect_interval),
+        ("scrape_interval", settings.scrape_interval),
+        ("scrape_timeout", settings.scrape_timeout),
+        ("resync_interval", settings.resync_interval),
+    ] {
+        if interval == Some(0) {
+            return Err(format!("{} must be at least 1 second", option));
+        }
+    }
+
+    Ok(())
+}
+
+// Agents without configuration get revision 0 and keep their local settings.
+fn empty_config() -> AgentConfig {
+    AgentConfig {
+        revision: 0,
+        applied_revision: None,
+        config: sqlx::types::Json(AgentSettings::default()),
+    }
+}
+
+async fn find_company_agent(
+    db: &sqlxPool<sqlx::Postgres>,
+    user: &CurrentUser,
+    agent_name: &str,
+) -> Result<Option<Agent>, AppError> {
+    let agent: Option<Agent> = sqlx::query_as::<_, Agent>(
+        "
+            SELECT id, name, token, id_company
+            FROM agent
+            WHERE id_company = $1 and name = $2
+        ",
+    )
+    .bind(user.id_company)
+    .bind(agent_name)
+    .fetch_optional(db)
+    .await?;
+    Ok(agent)
+}
+
+mod get {
+    use axum_login::tracing::debug;
+
+    use super::*;
+
+    pub async fn agent_config(
+        Extension(agent): Extension<Agent>,
+        State(db): State<sqlxPool<sqlx::Postgres>>,
+        headers: HeaderMap,
+    ) -> Result<(StatusCode, Json<AgentConfig>), AppError> {
+        let running_revision = headers
+            .get(REVISION_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<i64>().ok());
+
+        if let Some(revision) = running_revision {
+            debug!("agent {} runs config revision {}", agent.name, revision);
+            sqlx::query(
+                "
+                    UPDATE agent_config SET applied_revision = $2
+                    WHERE id_agent = $1
+                ",
+            )
+            .bind(agent.id)
+            .bind(revision)
+            .execute(&db)
+            .await?;
+        }
+
+        let config: Option<AgentConfig> = sqlx::query_as::<_, AgentConfig>(
+            "
+                SELECT revision, applied_revision, config
+                FROM agent_config
+                WHERE id_agent = $1
+            ",
+        )
+        .bind(agent.id)
+        .fetch_optional(&db)
+        .await?;
+
+        Ok((StatusCode::OK, Json(config.unwrap_or_else(empty_config))))
+    }
+
+    pub async fn config(
+        Path(agent_name): Path<String>,
+        user: CurrentUser,
+        State(db): State<sqlxPool<sqlx::Postgres>>,
+    ) -> Result<(StatusCode, Json<Option<AgentConfig>>), AppError> {
+        // If no agent found (or agent for another company) -> 404
+        let Some(agent) = find_company_agent(&db, &user, &agent_name).await? else {
+            return Ok((StatusCode::NOT_FOUND, Json(None)));
+        };
+
+        let config: Option<AgentConfig> = sqlx::query_as::<_, AgentConfig>(
+            "
+                SELECT revision, applied_revision, config
+                FROM agent_config
+                WHERE id_agent = $1
+            ",
+        )
+        .bind(agent.id)
+        .fetch_optional(&db)
+        .await?;
+
+        Ok((
+            StatusCode::OK,
+            Json(Some(config.unwrap_or_else(empty_config))),
+        ))
+    }
+}
+
+mod put {
+    use super::*;
+
+    pub async fn config(
+        Path(agent_name): Path<String>,
+        user: CurrentUser,
+        State(db): State<sqlxPool<sqlx::Postgres>>,
+        extract::Json(settings): extract::Json<AgentSettings>,
+    ) -> Result<(StatusCode, Json<Option<AgentConfig>>), AppError> {
+        let Some(agent) = find_company_agent(&db, &user, &agent_name).await? else {
+            return Ok((StatusCode::NOT_FOUND, Json(None)));
+        };
+        validate_settings(&settings).map_err(AppError::InvalidPayload)?;
+
+        // Every change is a new revision, so the agents notice it.
+        let config: AgentConfig = sqlx::query_as::<_, AgentConfig>(
+            "
+                INSERT INTO agent_config(id_agent, config)
+                values($1,$2)
+                ON CONFLICT (id_agent) DO UPDATE
+                    SET config = EXCLUDED.config, revision = agent_config.revision + 1
+                RETURNING revision, applied_revision, config
+            ",
+        )
+        .bind(agent.id)
+        .bind(sqlx::types::Json(settings))
+        .fetch_one(&db)
+        .await?;
+
+        Ok((StatusCode::OK, Json(Some(config))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(json: &str) -> AgentSettings {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn valid_settings() {
+        let settings = settings(
+            r#"{
+                "exclude": "exited|dead",
+                "include_name": "^web-",
+                "include_labels": ["com.example.monitor=true", "team"],
+                "labels": ["com.example.team=team", "com.example.owner"],
+                "stats_interval": 10,
+                "scrape_timeout": 5
+            }"#,
+        );
+
+        assert_eq!(validate_settings(&settings), Ok(()));
+        assert_eq!(validate_settings(&AgentSettings::default()), Ok(()));
+    }
+
+    #[test]
+    fn invalid_settings() {
+        for json in [
+            r#"{"exclude": "(unclosed"}"#,
+            r#"{"include_image": "[a-"}"#,
+            r#"{"exclude_labels": ["=value"]}"#,
+            r#"{"labels": ["=team"]}"#,
+            r#"{"labels": ["com.example.team=1team"]}"#,
+            r#"{"labels": ["com.example.team=__team"]}"#,
+            r#"{"labels": ["com.example.team=host"]}"#,
+            r#"{"labels": ["com.example.team=job"]}"#,
+            r#"{"labels": ["com.example.team=compose_team"]}"#,
+            r#"{"labels": ["com.example.team=exported_job"]}"#,
+            r#"{"labels": ["com.example.team=interface"]}"#,
+            r#"{"labels": ["com.example.team=mountpoint"]}"#,
+            r#"{"labels": ["image"]}"#,
+            r#"{"stats_interval": 0}"#,
+            r#"{"resync_interval": 0}"#,
+        ] {
+            assert!(validate_settings(&settings(json)).is_err(), "{}", json);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use axum::{
+    Extension, Json, Router,
+    extract::{self, Query, State},
+    http::StatusCode,
+    routing::{get, post},
+};
+use serde::Deserialize;
+use sqlx::Pool as sqlxPool;
+
+use super::super::super::{
+    model::{Agent, AppError, ContainerEvent, NewContainerEvent},
+    web::{App, extractor::current_user::CurrentUser},
+};
+
+// Routes used by the agents, they must be behind the agent token validation.
+pub fn agent_router() -> Router<App> {
+    Router::new().route("/container-events", post(self::post::insert))
+}
+
+pub fn router() -> Router<App> {
+    Router::new().route("/events", get(self::get::events))
+}
+
+// Filters of the timeline, timestamps are unix milliseconds.
+#[derive(Debug, Deserialize)]
+pub struct EventsQuery {
+    start: Option<i64>,
+    end: Option<i64>,
+    // container name or id.
+    container: Option<String>,
+    // agent name.
+    agent: Option<String>,
+    limit: Option<i64>,
+}
+
+mod post {
+    use axum_login::tracing::debug;
+
+    use super::*;
+
+    pub async fn insert(
+        Extension(agent): Extension<Agent>,
+        State(db): State<sqlxPool<sqlx::Postgres>>,
+        extract::Json(events): extract::Json<Vec<NewContainerEvent>>,
+    ) -> Result<StatusCode, AppError> {
+        debug!(
+            "agent {} sent {} container events",
+            agent.name,
+            events.len()
+        );
+
+        let mut transaction = db.begin().await?;
+        for event in events {
+            if event.container_id.is_empty() || event.action.is_empty() {
+                return Err(AppError::EmptyArgument);
+            }
+
+            sqlx::query(
+                "
+                    INSERT INTO container_event(id_company, id_agent, container_id, container_name, image, action, exit_code, oom_killed, timestamp)
+                    values($1,$2,$3,$4,$5,$6,$7,$8,$9)
+                ",
+            )
+            .bind(agent.id_company)
+            .bind(agent.id)
+            .bind(event.container_id)
+            .bind(event.container_name)
+            .bind(event.image)
+            .bind(event.action)
+            .bind(event.exit_code)
+            .bind(event.oom_killed)
+            .bind(event.timestamp)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+
+        Ok(StatusCode::CREATED)
+    }
+}
+
+mod get {
+    use super::*;
+
+    pub async fn events(
+        user: CurrentUser,
+        State(db): State<sqlxPool<sqlx::Postgres>>,
+        Query(query): Query<EventsQuery>,
+    ) -> Result<(StatusCode, Json<Vec<ContainerEvent>>), AppError> {
+        // Only the events of the user company are visible.
+        let events: Vec<ContainerEvent> = sqlx::query_as::<_, ContainerEvent>(
+            "
+                SELECT e.id, a.name as agent_name, e.container_id, e.container_name, e.image, e.action, e.exit_code, e.oom_killed, e.timestamp
+                FROM container_event e
+                JOIN agent a ON a.id = e.id_agent
+                WHERE e.id_company = $1
+                    AND ($2::bigint IS NULL OR e.timestamp >= $2)
+                    AND ($3::bigint IS NULL OR e.timestamp <= $3)
+                    AND ($4::text IS NULL OR e.container_name = $4 OR e.container_id = $4)
+                    AND ($5::text IS NULL OR a.name = $5)
+                ORDER BY e.timestamp DESC
+                LIMIT $6
+            ",
+        )
+        .bind(user.id_company)
+        .bind(query.start)
+        .bind(query.end)
+        .bind(query.container)
+        .bind(query.agent)
+        .bind(query.limit.unwrap_or(1000).clamp(1, 10000))
+        .fetch_all(&db)
+        .await?;
+
+        Ok((StatusCode::OK, Json(events)))
+    }
+}
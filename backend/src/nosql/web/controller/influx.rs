@@ -0,0 +1,367 @@
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    Extension, Router,
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use serde::Deserialize;
+use sqlx::Pool as sqlxPool;
+
+use super::super::super::{
+    model::{Agent, AppError, VictoriaMetric},
+    validation::sanitize_name,
+    web::{
+        App,
+        app::{IngestLimits, VictoriaEndpoint},
+        extractor::decoded_body::DecodedBody,
+    },
+};
+
+// InfluxDB line protocol, used by Telegraf with the `influxdb` output and `urls = ["http://backend:3000/influx"]`.
+// Routes used by the agents, they must be behind the agent token validation.
+pub fn router() -> Router<App> {
+    Router::new().route("/influx/write", post(self::post::write))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WriteQuery {
+    // Unit of the timestamps: ns (default), u, ms, s, m or h.
+    precision: Option<String>,
+}
+
+/// Parse InfluxDB line protocol into `VictoriaMetric` records.
+///
+/// Like VictoriaMetrics, every field is a series named `{measurement}_{field}` with the tags as labels,
+/// the names are converted to the Prometheus charset.
+/// String fields are skipped, booleans are sent as 0 or 1. Lines without timestamp get `default_timestamp`, in milliseconds.
+pub fn parse_line_protocol(
+    text: &str,
+    precision: &str,
+    default_timestamp: i64,
+) -> Result<Vec<VictoriaMetric>, AppError> {
+    // `None` when the timestamp does not fit in milliseconds.
+    let to_millis: fn(i64) -> Option<i64> = match precision {
+        "ns" | "n" => |t| Some(t / 1_000_000),
+        "us" | "u" => |t| Some(t / 1_000),
+        "ms" => Some,
+        "s" => |t| t.checked_mul(1_000),
+        "m" => |t| t.checked_mul(60_000),
+        "h" => |t| t.checked_mul(3_600_000),
+        _ => {
+            return Err(AppError::InvalidPayload(format!(
+                "unknown precision \"{}\"",
+                precision
+            )));
+        }
+    };
+
+    let mut metrics = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let invalid =
+            |reason: &str| AppError::InvalidPayload(format!("line {}: {}", index + 1, reason));
+
+        // Double quotes only delimit strings in the field set, they are plain characters in the measurement and tags.
+        let (series, rest) = split_once_unescaped(line, ' ', false)
+            .ok_or_else(|| invalid("expected measurement, fields and timestamp"))?;
+        let (fields, timestamp) = match split_unescaped(rest, ' ', true).as_slice() {
+            [fields] => (*fields, None),
+            [fields, timestamp] => (*fields, Some(*timestamp)),
+            _ => return Err(invalid("expected measurement, fields and timestamp")),
+        };
+
+        let timestamp = match timestamp {
+            Some(timestamp) => to_millis(
+                timestamp
+                    .parse::<i64>()
+                    .map_err(|_| invalid("invalid timestamp"))?,
+            )
+            .ok_or_else(|| invalid("timestamp out of range"))?,
+            None => default_timestamp,
+        };
+
+        let mut series_parts = split_unescaped(series, ',', false).into_iter();
+        let measurement = unescape(series_parts.next().unwrap_or_default());
+        if measurement.is_empty() {
+            return Err(invalid("missing measurement"));
+        }
+
+        let mut tags = HashMap::new();
+        for tag in series_parts {
+            let (key, value) =
+                split_once_unescaped(tag, '=', false).ok_or_else(|| invalid("invalid tag"))?;
+            tags.insert(sanitize_name(&unescape(key), false), unescape(value));
+        }
+
+        for field in split_unescaped(fields, ',', true) {
+            let (key, value) =
+                split_once_unescaped(field, '=', false).ok_or_else(|| invalid("invalid field"))?;
+
+            let Some(value) =
+                parse_field_value(value).map_err(|_| invalid("invalid field value"))?
+            else {
+                continue;
+            };
+
+            let mut metric = tags.clone();
+            metric.insert(
+                "__name__".to_string(),
+                sanitize_name(&format!("{}_{}", measurement, unescape(key)), true),
+            );
+
+            metrics.push(VictoriaMetric {
+                metric,
+                values: vec![value],
+                timestamps: vec![timestamp],
+            });
+        }
+    }
+
+    Ok(metrics)
+}
+
+// Numeric value of a field, `None` for strings.
+fn parse_field_value(value: &str) -> Result<Option<f64>, ()> {
+    if value.starts_with('"') {
+        return Ok(None);
+    }
+
+    let number = match value {
+        "t" | "T" | "true" | "True" | "TRUE" => 1.0,
+        "f" | "F" | "false" | "False" | "FALSE" => 0.0,
+        _ => {
+            if let Some(integer) = value.strip_suffix('i') {
+                integer.parse::<i64>().map_err(|_| ())? as f64
+            } else if let Some(unsigned) = value.strip_suffix('u') {
+                unsigned.parse::<u64>().map_err(|_| ())? as f64
+            } else {
+                value.parse::<f64>().map_err(|_| ())?
+            }
+        }
+    };
+
+    Ok(Some(number))
+}
+
+// Split on `separator` outside of backslash escapes, and of double quoted strings when `quotes` is set.
+fn split_unescaped(text: &str, separator: char, quotes: bool) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    let mut quoted = false;
+
+    for (index, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+
+        match c {
+            '\\' => escaped = true,
+            '"' if quotes => quoted = !quoted,
+            c if c == separator && !quoted => {
+                parts.push(&text[start..index]);
+                start = index + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+
+    parts
+}
+
+fn split_once_unescaped(text: &str, separator: char, quotes: bool) -> Option<(&str, &str)> {
+    match split_unescaped(text, separator, quotes).as_slice() {
+        [key, ..] if key.len() < text.len() => Some((key, &text[key.len() + 1..])),
+        _ => None,
+    }
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '\\'
+            && let Some(next) = chars.peek()
+            && matches!(next, ',' | ' ' | '=' | '"' | '\\')
+        {
+            unescaped.push(*next);
+            chars.next();
+            continue;
+        }
+        unescaped.push(c);
+    }
+
+    unescaped
+}
+
+mod post {
+    use axum_login::tracing::debug;
+
+    use super::super::victoria_api::post::import_metrics;
+    use super::*;
+
+    pub async fn write(
+        Extension(agent): Extension<Agent>,
+        State(db): State<sqlxPool<sqlx::Postgres>>,
+        State(client): State<reqwest::Client>,
+        State(vm): State<VictoriaEndpoint>,
+        State(limits): State<IngestLimits>,
+        Query(query): Query<WriteQuery>,
+        DecodedBody(body): DecodedBody,
+    ) -> Result<Response, AppError> {
+        let text =
+            std::str::from_utf8(&body).map_err(|e| AppError::InvalidPayload(e.to_string()))?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+
+        let metrics = parse_line_protocol(text, query.precision.as_deref().unwrap_or("ns"), now)?;
+        debug!("agent {} sent {} influx samples", agent.name, metrics.len());
+        if metrics.is_empty() {
+            return Ok(StatusCode::NO_CONTENT.into_response());
+        }
+
+        let report = import_metrics(db, &client, &vm, &limits, &agent, metrics).await?;
+
+        Ok(report.into_response(StatusCode::NO_CONTENT))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000_000;
+
+    fn parse(text: &str, precision: &str) -> Vec<VictoriaMetric> {
+        let mut metrics = parse_line_protocol(text, precision, NOW).unwrap();
+        metrics.sort_by(|a, b| a.metric["__name__"].cmp(&b.metric["__name__"]));
+        metrics
+    }
+
+    #[test]
+    fn fields_become_series() {
+        let metrics = parse(
+            "cpu,host=server01,region=eu usage_idle=92.5,usage_user=3i,up=true 1700000000000000000",
+            "ns",
+        );
+
+        assert_eq!(metrics.len(), 3);
+        assert_eq!(metrics[0].metric["__name__"], "cpu_up");
+        assert_eq!(metrics[0].values, [1.0]);
+        assert_eq!(metrics[1].metric["__name__"], "cpu_usage_idle");
+        assert_eq!(metrics[1].metric["host"], "server01");
+        assert_eq!(metrics[1].metric["region"], "eu");
+        assert_eq!(metrics[1].values, [92.5]);
+        assert_eq!(metrics[1].timestamps, [NOW]);
+        assert_eq!(metrics[2].values, [3.0]);
+    }
+
+    #[test]
+    fn escapes() {
+        let metrics = parse(
+            r#"disk\ io,path=C:\\data,mount\ point=a\,b\=c read\ bytes=1"#,
+            "ns",
+        );
+
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].metric["__name__"], "disk_io_read_bytes");
+        assert_eq!(metrics[0].metric["path"], r"C:\data");
+        assert_eq!(metrics[0].metric["mount_point"], "a,b=c");
+    }
+
+    #[test]
+    fn quoted_fields() {
+        let metrics = parse(
+            r#"log,source="app" message="a, b = c d",level="x\"y",count=2i 1700000000000000000"#,
+            "ns",
+        );
+
+        // String fields are skipped, their spaces, commas and equals signs do not split the line.
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].metric["__name__"], "log_count");
+        assert_eq!(metrics[0].metric["source"], "\"app\"");
+        assert_eq!(metrics[0].values, [2.0]);
+        assert_eq!(metrics[0].timestamps, [NOW]);
+    }
+
+    #[test]
+    fn precisions() {
+        for (precision, timestamp) in [
+            ("ns", "1700000000000000000"),
+            ("n", "1700000000000000000"),
+            ("us", "1700000000000000"),
+            ("u", "1700000000000000"),
+            ("ms", "1700000000000"),
+            ("s", "1700000000"),
+        ] {
+            let metrics = parse(&format!("m value=1 {}", timestamp), precision);
+            assert_eq!(metrics[0].timestamps, [NOW], "precision {}", precision);
+        }
+
+        let metrics = parse("m value=1 28333333", "m");
+        assert_eq!(metrics[0].timestamps, [1_699_999_980_000]);
+        let metrics = parse("m value=1 472222", "h");
+        assert_eq!(metrics[0].timestamps, [1_699_999_200_000]);
+
+        // Without timestamp, the time of the request.
+        let metrics = parse("m value=1", "s");
+        assert_eq!(metrics[0].timestamps, [NOW]);
+
+        assert!(matches!(
+            parse_line_protocol("m value=1 1", "d", NOW),
+            Err(AppError::InvalidPayload(_))
+        ));
+    }
+
+    #[test]
+    fn timestamp_overflow() {
+        for (precision, timestamp) in [
+            ("s", "9223372036854776"),
+            ("m", "153722867280913"),
+            ("h", "2562047788016"),
+        ] {
+            let result = parse_line_protocol(&format!("m value=1 {}", timestamp), precision, NOW);
+            assert!(
+                matches!(result, Err(AppError::InvalidPayload(_))),
+                "precision {}",
+                precision
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_lines() {
+        for line in [
+            "m",
+            "m value",
+            "m value=x",
+            ",host=a value=1",
+            "m value=1 now",
+        ] {
+            assert!(
+                matches!(
+                    parse_line_protocol(line, "ns", NOW),
+                    Err(AppError::InvalidPayload(_))
+                ),
+                "line {:?}",
+                line
+            );
+        }
+    }
+}
@@ -0,0 +1,807 @@
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    Extension, Json, Router,
+    extract::State,
+    http::{
+        HeaderMap, StatusCode,
+        header::{self, CONTENT_TYPE},
+    },
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use prost::Message;
+use sqlx::Pool as sqlxPool;
+
+use super::super::super::{
+    model::{Agent, AppError, VictoriaMetric},
+    validation::sanitize_name,
+    web::{
+        App,
+        app::{IngestLimits, VictoriaEndpoint},
+        extractor::decoded_body::DecodedBody,
+    },
+};
+
+// OpenTelemetry protocol over HTTP, used by the OpenTelemetry SDKs and collector with the `otlphttp` exporter.
+// Routes used by the agents, they must be behind the agent token validation.
+pub fn router() -> Router<App> {
+    Router::new().route("/v1/metrics", post(self::post::metrics))
+}
+
+// Messages of opentelemetry/proto/collector/metrics/v1 and opentelemetry/proto/metrics/v1, only the fields
+// needed for gauges, sums and histograms, the others are skipped by prost and serde.
+// The same structs read the JSON encoding, where the 64 bits integers are strings.
+mod proto {
+    use serde::{Deserialize, Deserializer, Serialize};
+    use std::str::FromStr;
+
+    #[derive(Clone, PartialEq, prost::Message, Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    pub struct ExportMetricsServiceRequest {
+        #[prost(message, repeated, tag = "1")]
+        pub resource_metrics: Vec<ResourceMetrics>,
+    }
+
+    // Answer of the export, with the number of data points refused by the validation.
+    #[derive(Clone, PartialEq, prost::Message, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ExportMetricsServiceResponse {
+        #[prost(message, optional, tag = "1")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub partial_success: Option<ExportMetricsPartialSuccess>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ExportMetricsPartialSuccess {
+        #[prost(int64, tag = "1")]
+        pub rejected_data_points: i64,
+        #[prost(string, tag = "2")]
+        pub error_message: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message, Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    pub struct ResourceMetrics {
+        #[prost(message, optional, tag = "1")]
+        pub resource: Option<Resource>,
+        #[prost(message, repeated, tag = "2")]
+        pub scope_metrics: Vec<ScopeMetrics>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message, Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    pub struct Resource {
+        #[prost(message, repeated, tag = "1")]
+        pub attributes: Vec<KeyValue>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message, Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    pub struct ScopeMetrics {
+        #[prost(message, repeated, tag = "2")]
+        pub metrics: Vec<Metric>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message, Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    pub struct Metric {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(oneof = "Data", tags = "5, 7, 9")]
+        #[serde(flatten)]
+        pub data: Option<Data>,
+    }
+
+    // Exponential histograms and summaries are not converted.
+    #[derive(Clone, PartialEq, prost::Oneof, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub enum Data {
+        #[prost(message, tag = "5")]
+        Gauge(Gauge),
+        #[prost(message, tag = "7")]
+        Sum(Sum),
+        #[prost(message, tag = "9")]
+        Histogram(Histogram),
+    }
+
+    #[derive(Clone, PartialEq, prost::Message, Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    pub struct Gauge {
+        #[prost(message, repeated, tag = "1")]
+        pub data_points: Vec<NumberDataPoint>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message, Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    pub struct Sum {
+        #[prost(message, repeated, tag = "1")]
+        pub data_points: Vec<NumberDataPoint>,
+        #[prost(int32, tag = "2")]
+        #[serde(deserialize_with = "temporality")]
+        pub aggregation_temporality: i32,
+        #[prost(bool, tag = "3")]
+        pub is_monotonic: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message, Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    pub struct Histogram {
+        #[prost(message, repeated, tag = "1")]
+        pub data_points: Vec<HistogramDataPoint>,
+        #[prost(int32, tag = "2")]
+        #[serde(deserialize_with = "temporality")]
+        pub aggregation_temporality: i32,
+    }
+
+    // Values of the AggregationTemporality enum.
+    pub const AGGREGATION_TEMPORALITY_DELTA: i32 = 1;
+    pub const AGGREGATION_TEMPORALITY_CUMULATIVE: i32 = 2;
+
+    #[derive(Clone, PartialEq, prost::Message, Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    pub struct NumberDataPoint {
+        #[prost(message, repeated, tag = "7")]
+        pub attributes: Vec<KeyValue>,
+        #[prost(fixed64, tag = "3")]
+        #[serde(deserialize_with = "integer")]
+        pub time_unix_nano: u64,
+        #[prost(oneof = "NumberValue", tags = "4, 6")]
+        #[serde(flatten)]
+        pub value: Option<NumberValue>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Oneof, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub enum NumberValue {
+        #[prost(double, tag = "4")]
+        AsDouble(f64),
+        #[prost(sfixed64, tag = "6")]
+        #[serde(deserialize_with = "integer")]
+        AsInt(i64),
+    }
+
+    #[derive(Clone, PartialEq, prost::Message, Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    pub struct HistogramDataPoint {
+        #[prost(message, repeated, tag = "9")]
+        pub attributes: Vec<KeyValue>,
+        #[prost(fixed64, tag = "3")]
+        #[serde(deserialize_with = "integer")]
+        pub time_unix_nano: u64,
+        #[prost(fixed64, tag = "4")]
+        #[serde(deserialize_with = "integer")]
+        pub count: u64,
+        #[prost(double, optional, tag = "5")]
+        pub sum: Option<f64>,
+        #[prost(fixed64, repeated, tag = "6")]
+        #[serde(deserialize_with = "integers")]
+        pub bucket_counts: Vec<u64>,
+        #[prost(double, repeated, tag = "7")]
+        pub explicit_bounds: Vec<f64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message, Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    pub struct KeyValue {
+        #[prost(string, tag = "1")]
+        pub key: String,
+        #[prost(message, optional, tag = "2")]
+        pub value: Option<AnyValue>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message, Deserialize)]
+    #[serde(default, rename_all = "camelCase")]
+    pub struct AnyValue {
+        #[prost(oneof = "AttributeValue", tags = "1, 2, 3, 4")]
+        #[serde(flatten)]
+        pub value: Option<AttributeValue>,
+    }
+
+    // Arrays, key-value lists and bytes can't be labels and are skipped.
+    // The variants keep the names of the proto, they are the keys of the JSON encoding.
+    #[allow(clippy::enum_variant_names)]
+    #[derive(Clone, PartialEq, prost::Oneof, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub enum AttributeValue {
+        #[prost(string, tag = "1")]
+        StringValue(String),
+        #[prost(bool, tag = "2")]
+        BoolValue(bool),
+        #[prost(int64, tag = "3")]
+        #[serde(deserialize_with = "integer")]
+        IntValue(i64),
+        #[prost(double, tag = "4")]
+        DoubleValue(f64),
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Integer<T> {
+        Number(T),
+        Text(String),
+    }
+
+    impl<T: FromStr> Integer<T> {
+        fn parse<E: serde::de::Error>(self) -> Result<T, E> {
+            match self {
+                Integer::Number(number) => Ok(number),
+                Integer::Text(text) => text
+                    .parse()
+                    .map_err(|_| E::custom(format!("invalid integer \"{}\"", text))),
+            }
+        }
+    }
+
+    fn integer<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de> + FromStr,
+    {
+        Integer::<T>::deserialize(deserializer)?.parse()
+    }
+
+    // The JSON encoding of enums is the number, some exporters send the name.
+    fn temporality<'de, D>(deserializer: D) -> Result<i32, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match Integer::<i32>::deserialize(deserializer)? {
+            Integer::Text(text) if text == "AGGREGATION_TEMPORALITY_UNSPECIFIED" => Ok(0),
+            Integer::Text(text) if text == "AGGREGATION_TEMPORALITY_DELTA" => {
+                Ok(AGGREGATION_TEMPORALITY_DELTA)
+            }
+            Integer::Text(text) if text == "AGGREGATION_TEMPORALITY_CUMULATIVE" => {
+                Ok(AGGREGATION_TEMPORALITY_CUMULATIVE)
+            }
+            integer => integer.parse(),
+        }
+    }
+
+    fn integers<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de> + FromStr,
+    {
+        Vec::<Integer<T>>::deserialize(deserializer)?
+            .into_iter()
+            .map(Integer::parse)
+            .collect()
+    }
+}
+
+/// Encoding of an OTLP request, read from its Content-Type.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OtlpFormat {
+    Protobuf,
+    Json,
+}
+
+impl OtlpFormat {
+    fn from_headers(headers: &HeaderMap) -> Result<Self, AppError> {
+        let content_type = headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+
+        match mime {
+            "application/x-protobuf" => Ok(OtlpFormat::Protobuf),
+            "application/json" => Ok(OtlpFormat::Json),
+            _ => Err(AppError::UnsupportedContentType(content_type.to_string())),
+        }
+    }
+}
+
+/// Samples of an OTLP export request, and the data points that could not be converted.
+#[derive(Debug, Default)]
+pub struct OtlpMetrics {
+    pub metrics: Vec<VictoriaMetric>,
+    pub skipped_points: usize,
+    // Reason of the first skipped data point, for the partial success message.
+    pub skip_reason: Option<String>,
+}
+
+impl OtlpMetrics {
+    // NaN and infinite values can't be stored, they are skipped like the invalid samples of the validation.
+    fn push(&mut self, name: &str, labels: HashMap<String, String>, value: f64, timestamp: i64) {
+        if !value.is_finite() {
+            self.skip(1, name, "value is NaN or infinite");
+            return;
+        }
+        let mut metric = labels;
+        metric.insert("__name__".to_string(), name.to_string());
+        self.metrics.push(VictoriaMetric {
+            metric,
+            values: vec![value],
+            timestamps: vec![timestamp],
+        });
+    }
+
+    fn skip(&mut self, points: usize, metric: &str, reason: &str) {
+        if points == 0 {
+            return;
+        }
+        self.skipped_points += points;
+        if self.skip_reason.is_none() {
+            self.skip_reason = Some(format!("{}: {}", metric, reason));
+        }
+    }
+}
+
+/// Decode an OTLP export request into `VictoriaMetric` records.
+///
+/// Gauges and sums give one series per data point, monotonic sums are counters named with the `_total` suffix.
+/// Histograms give the Prometheus `_bucket` (cumulative, with a `le` label), `_count` and `_sum` series.
+/// Resource attributes and data point attributes become labels, with the names
+/// converted to the Prometheus charset (`service.name` becomes `service_name`).
+/// Sums and histograms with the delta temporality can't be stored as Prometheus counters, they are skipped.
+pub fn parse_export_request(
+    body: &[u8],
+    format: OtlpFormat,
+    default_timestamp: i64,
+) -> Result<OtlpMetrics, AppError> {
+    let request = match format {
+        OtlpFormat::Protobuf => proto::ExportMetricsServiceRequest::decode(body)
+            .map_err(|e| AppError::InvalidPayload(e.to_string()))?,
+        OtlpFormat::Json => {
+            serde_json::from_slice(body).map_err(|e| AppError::InvalidPayload(e.to_string()))?
+        }
+    };
+
+    let mut converted = OtlpMetrics::default();
+
+    for resource_metrics in request.resource_metrics {
+        let resource_labels = labels(
+            &HashMap::new(),
+            &resource_metrics.resource.unwrap_or_default().attributes,
+        );
+
+        for metric in resource_metrics
+            .scope_metrics
+            .into_iter()
+            .flat_map(|scope| scope.metrics)
+        {
+            let mut name = sanitize_name(&metric.name, true);
+            let data_points = match metric.data {
+                Some(proto::Data::Gauge(gauge)) => gauge.data_points,
+                Some(proto::Data::Sum(sum)) => {
+                    if sum.aggregation_temporality == proto::AGGREGATION_TEMPORALITY_DELTA {
+                        converted.skip(sum.data_points.len(), &name, DELTA_REASON);
+                        continue;
+                    }
+                    if sum.is_monotonic && !name.ends_with("_total") {
+                        name.push_str("_total");
+                    }
+                    sum.data_points
+                }
+                Some(proto::Data::Histogram(histogram)) => {
+                    if histogram.aggregation_temporality == proto::AGGREGATION_TEMPORALITY_DELTA {
+                        converted.skip(histogram.data_points.len(), &name, DELTA_REASON);
+                        continue;
+                    }
+
+                    for point in histogram.data_points {
+                        let point_labels = labels(&resource_labels, &point.attributes);
+
+                        // OTLP counts the samples of each bucket, Prometheus counts the samples up to each bound.
+                        let mut cumulative = 0;
+                        for (bound, count) in point.explicit_bounds.iter().zip(&point.bucket_counts)
+                        {
+                            cumulative += count;
+                            let mut bucket_labels = point_labels.clone();
+                            bucket_labels.insert("le".to_string(), bound.to_string());
+                            converted.push(
+                                &format!("{}_bucket", name),
+                                bucket_labels,
+                                cumulative as f64,
+                                timestamp(point.time_unix_nano, default_timestamp),
+                            );
+                        }
+                        let mut bucket_labels = point_labels.clone();
+                        bucket_labels.insert("le".to_string(), "+Inf".to_string());
+                        converted.push(
+                            &format!("{}_bucket", name),
+                            bucket_labels,
+                            point.count as f64,
+                            timestamp(point.time_unix_nano, default_timestamp),
+                        );
+
+                        converted.push(
+                            &format!("{}_count", name),
+                            point_labels.clone(),
+                            point.count as f64,
+                            timestamp(point.time_unix_nano, default_timestamp),
+                        );
+                        if let Some(sum) = point.sum {
+                            converted.push(
+                                &format!("{}_sum", name),
+                                point_labels,
+                                sum,
+                                timestamp(point.time_unix_nano, default_timestamp),
+                            );
+                        }
+                    }
+                    continue;
+                }
+                None => continue,
+            };
+
+            for point in data_points {
+                let value = match point.value {
+                    Some(proto::NumberValue::AsDouble(value)) => value,
+                    Some(proto::NumberValue::AsInt(value)) => value as f64,
+                    None => continue,
+                };
+                converted.push(
+                    &name,
+                    labels(&resource_labels, &point.attributes),
+                    value,
+                    timestamp(point.time_unix_nano, default_timestamp),
+                );
+            }
+        }
+    }
+
+    Ok(converted)
+}
+
+const DELTA_REASON: &str = "delta temporality is not supported, export cumulative data";
+
+// Labels of `base` with the attributes added, the attributes win on conflicts.
+fn labels(
+    base: &HashMap<String, String>,
+    attributes: &[proto::KeyValue],
+) -> HashMap<String, String> {
+    let mut labels = base.clone();
+
+    for attribute in attributes {
+        let value = match attribute
+            .value
+            .as_ref()
+            .and_then(|value| value.value.as_ref())
+        {
+            Some(proto::AttributeValue::StringValue(value)) => value.clone(),
+            Some(proto::AttributeValue::BoolValue(value)) => value.to_string(),
+            Some(proto::AttributeValue::IntValue(value)) => value.to_string(),
+            Some(proto::AttributeValue::DoubleValue(value)) => value.to_string(),
+            None => continue,
+        };
+        labels.insert(sanitize_name(&attribute.key, false), value);
+    }
+
+    labels
+}
+
+// Data points without time get the reception time.
+fn timestamp(time_unix_nano: u64, default_timestamp: i64) -> i64 {
+    if time_unix_nano == 0 {
+        default_timestamp
+    } else {
+        (time_unix_nano / 1_000_000) as i64
+    }
+}
+
+mod post {
+    use axum_login::tracing::debug;
+
+    use super::super::victoria_api::post::import_metrics;
+    use super::*;
+
+    pub async fn metrics(
+        Extension(agent): Extension<Agent>,
+        State(db): State<sqlxPool<sqlx::Postgres>>,
+        State(client): State<reqwest::Client>,
+        State(vm): State<VictoriaEndpoint>,
+        State(limits): State<IngestLimits>,
+        headers: HeaderMap,
+        DecodedBody(body): DecodedBody,
+    ) -> Result<Response, AppError> {
+        let format = OtlpFormat::from_headers(&headers)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+
+        let OtlpMetrics {
+            metrics,
+            mut skipped_points,
+            skip_reason,
+        } = parse_export_request(&body, format, now)?;
+        debug!("agent {} sent {} OTLP samples", agent.name, metrics.len());
+        let mut error_message = skip_reason;
+        if !metrics.is_empty() {
+            let report = import_metrics(db, &client, &vm, &limits, &agent, metrics).await?;
+            skipped_points += report.rejected_samples;
+            if let Some(rejection) = report.rejected.first() {
+                error_message
+                    .get_or_insert_with(|| format!("{}: {}", rejection.metric, rejection.reason));
+            }
+        }
+
+        // OTLP only has a count and a message for the refused data points, the message tells the first reason.
+        let mut response = proto::ExportMetricsServiceResponse::default();
+        if skipped_points > 0 {
+            response.partial_success = Some(proto::ExportMetricsPartialSuccess {
+                rejected_data_points: skipped_points as i64,
+                error_message: error_message.unwrap_or_default(),
+            });
+        }
+
+        // The answer is in the encoding of the request.
+        let response = match format {
+            OtlpFormat::Protobuf => (
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, "application/x-protobuf")],
+                response.encode_to_vec(),
+            )
+                .into_response(),
+            OtlpFormat::Json => (StatusCode::OK, Json(response)).into_response(),
+        };
+
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000_000;
+    const TIME: u64 = 1_600_000_000_000_000_000;
+
+    fn attribute(key: &str, value: &str) -> proto::KeyValue {
+        proto::KeyValue {
+            key: key.to_string(),
+            value: Some(proto::AnyValue {
+                value: Some(proto::AttributeValue::StringValue(value.to_string())),
+            }),
+        }
+    }
+
+    fn number_point(value: f64) -> proto::NumberDataPoint {
+        proto::NumberDataPoint {
+            attributes: vec![attribute("http.method", "GET")],
+            time_unix_nano: TIME,
+            value: Some(proto::NumberValue::AsDouble(value)),
+        }
+    }
+
+    fn request(metrics: Vec<proto::Metric>) -> Vec<u8> {
+        proto::ExportMetricsServiceRequest {
+            resource_metrics: vec![proto::ResourceMetrics {
+                resource: Some(proto::Resource {
+                    attributes: vec![attribute("service.name", "shop")],
+                }),
+                scope_metrics: vec![proto::ScopeMetrics { metrics }],
+            }],
+        }
+        .encode_to_vec()
+    }
+
+    fn metric(name: &str, data: proto::Data) -> proto::Metric {
+        proto::Metric {
+            name: name.to_string(),
+            data: Some(data),
+        }
+    }
+
+    fn find<'a>(metrics: &'a [VictoriaMetric], name: &str) -> Vec<&'a VictoriaMetric> {
+        metrics
+            .iter()
+            .filter(|metric| metric.metric["__name__"] == name)
+            .collect()
+    }
+
+    #[test]
+    fn protobuf_gauge() {
+        let body = request(vec![metric(
+            "queue.size",
+            proto::Data::Gauge(proto::Gauge {
+                data_points: vec![number_point(12.0)],
+            }),
+        )]);
+
+        let converted = parse_export_request(&body, OtlpFormat::Protobuf, NOW).unwrap();
+
+        assert_eq!(converted.metrics.len(), 1);
+        let gauge = &converted.metrics[0];
+        assert_eq!(gauge.metric["__name__"], "queue_size");
+        assert_eq!(gauge.metric["service_name"], "shop");
+        assert_eq!(gauge.metric["http_method"], "GET");
+        assert_eq!(gauge.values, [12.0]);
+        assert_eq!(gauge.timestamps, [1_600_000_000_000]);
+        assert_eq!(converted.skipped_points, 0);
+    }
+
+    #[test]
+    fn protobuf_sums() {
+        let body = request(vec![
+            metric(
+                "http.requests",
+                proto::Data::Sum(proto::Sum {
+                    data_points: vec![number_point(5.0)],
+                    aggregation_temporality: proto::AGGREGATION_TEMPORALITY_CUMULATIVE,
+                    is_monotonic: true,
+                }),
+            ),
+            metric(
+                "connections",
+                proto::Data::Sum(proto::Sum {
+                    data_points: vec![number_point(3.0)],
+                    aggregation_temporality: proto::AGGREGATION_TEMPORALITY_CUMULATIVE,
+                    is_monotonic: false,
+                }),
+            ),
+            metric(
+                "bytes_total",
+                proto::Data::Sum(proto::Sum {
+                    data_points: vec![number_point(7.0)],
+                    aggregation_temporality: proto::AGGREGATION_TEMPORALITY_CUMULATIVE,
+                    is_monotonic: true,
+                }),
+            ),
+            metric(
+                "errors",
+                proto::Data::Sum(proto::Sum {
+                    data_points: vec![number_point(1.0), number_point(2.0)],
+                    aggregation_temporality: proto::AGGREGATION_TEMPORALITY_DELTA,
+                    is_monotonic: true,
+                }),
+            ),
+        ]);
+
+        let converted = parse_export_request(&body, OtlpFormat::Protobuf, NOW).unwrap();
+
+        // Monotonic sums are counters, the others are gauges.
+        assert_eq!(
+            find(&converted.metrics, "http_requests_total")[0].values,
+            [5.0]
+        );
+        assert_eq!(find(&converted.metrics, "connections")[0].values, [3.0]);
+        assert_eq!(find(&converted.metrics, "bytes_total")[0].values, [7.0]);
+
+        // Delta sums are skipped and reported.
+        assert_eq!(converted.metrics.len(), 3);
+        assert_eq!(converted.skipped_points, 2);
+        assert!(converted.skip_reason.unwrap().starts_with("errors: delta"));
+    }
+
+    #[test]
+    fn protobuf_histogram() {
+        let body = request(vec![metric(
+            "latency",
+            proto::Data::Histogram(proto::Histogram {
+                data_points: vec![proto::HistogramDataPoint {
+                    attributes: vec![],
+                    time_unix_nano: TIME,
+                    count: 6,
+                    sum: Some(1.5),
+                    bucket_counts: vec![1, 2, 3],
+                    explicit_bounds: vec![0.1, 0.5],
+                }],
+                aggregation_temporality: proto::AGGREGATION_TEMPORALITY_CUMULATIVE,
+            }),
+        )]);
+
+        let converted = parse_export_request(&body, OtlpFormat::Protobuf, NOW).unwrap();
+
+        let buckets = find(&converted.metrics, "latency_bucket");
+        let bucket = |le: &str| {
+            buckets
+                .iter()
+                .find(|bucket| bucket.metric["le"] == le)
+                .unwrap()
+                .values[0]
+        };
+        assert_eq!(buckets.len(), 3);
+        assert_eq!(bucket("0.1"), 1.0);
+        assert_eq!(bucket("0.5"), 3.0);
+        assert_eq!(bucket("+Inf"), 6.0);
+        assert_eq!(find(&converted.metrics, "latency_count")[0].values, [6.0]);
+        assert_eq!(find(&converted.metrics, "latency_sum")[0].values, [1.5]);
+    }
+
+    #[test]
+    fn json_gauge_sum_and_histogram() {
+        let body = r#"{
+            "resourceMetrics": [{
+                "resource": {"attributes": [{"key": "service.name", "value": {"stringValue": "shop"}}]},
+                "scopeMetrics": [{
+                    "metrics": [
+                        {
+                            "name": "queue.size",
+                            "gauge": {"dataPoints": [{"timeUnixNano": "1600000000000000000", "asInt": "12"}]}
+                        },
+                        {
+                            "name": "http.requests",
+                            "sum": {
+                                "aggregationTemporality": 2,
+                                "isMonotonic": true,
+                                "dataPoints": [{
+                                    "attributes": [{"key": "code", "value": {"intValue": "200"}}],
+                                    "asDouble": 5
+                                }]
+                            }
+                        },
+                        {
+                            "name": "errors",
+                            "sum": {
+                                "aggregationTemporality": "AGGREGATION_TEMPORALITY_DELTA",
+                                "isMonotonic": true,
+                                "dataPoints": [{"asDouble": 1}]
+                            }
+                        },
+                        {
+                            "name": "latency",
+                            "histogram": {
+                                "aggregationTemporality": 2,
+                                "dataPoints": [{
+                                    "count": "3",
+                                    "sum": 0.7,
+                                    "bucketCounts": ["1", "2"],
+                                    "explicitBounds": [0.5]
+                                }]
+                            }
+                        }
+                    ]
+                }]
+            }]
+        }"#;
+
+        let converted = parse_export_request(body.as_bytes(), OtlpFormat::Json, NOW).unwrap();
+
+        let gauge = find(&converted.metrics, "queue_size")[0];
+        assert_eq!(gauge.values, [12.0]);
+        assert_eq!(gauge.timestamps, [1_600_000_000_000]);
+        assert_eq!(gauge.metric["service_name"], "shop");
+
+        let counter = find(&converted.metrics, "http_requests_total")[0];
+        assert_eq!(counter.values, [5.0]);
+        assert_eq!(counter.metric["code"], "200");
+        // Data points without time get the reception time.
+        assert_eq!(counter.timestamps, [NOW]);
+
+        assert!(find(&converted.metrics, "errors").is_empty());
+        assert_eq!(converted.skipped_points, 1);
+
+        assert_eq!(find(&converted.metrics, "latency_bucket").len(), 2);
+        assert_eq!(find(&converted.metrics, "latency_count")[0].values, [3.0]);
+        assert_eq!(find(&converted.metrics, "latency_sum")[0].values, [0.7]);
+    }
+
+    #[test]
+    fn non_finite_points_are_reported() {
+        let body = request(vec![metric(
+            "ratio",
+            proto::Data::Gauge(proto::Gauge {
+                data_points: vec![number_point(f64::NAN), number_point(0.5)],
+            }),
+        )]);
+
+        let converted = parse_export_request(&body, OtlpFormat::Protobuf, NOW).unwrap();
+
+        assert_eq!(converted.metrics.len(), 1);
+        assert_eq!(converted.skipped_points, 1);
+        assert_eq!(
+            converted.skip_reason.as_deref(),
+            Some("ratio: value is NaN or infinite")
+        );
+    }
+
+    #[test]
+    fn invalid_bodies() {
+        assert!(matches!(
+            parse_export_request(b"\xff\xff", OtlpFormat::Protobuf, NOW),
+            Err(AppError::InvalidPayload(_))
+        ));
+        assert!(matches!(
+            parse_export_request(b"{\"resourceMetrics\": 1}", OtlpFormat::Json, NOW),
+            Err(AppError::InvalidPayload(_))
+        ));
+    }
+}
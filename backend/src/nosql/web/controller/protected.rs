@@ -62,7 +62,7 @@ mod victoria_api {
     ) -> Result<(http::StatusCode, Bytes), http::StatusCode> {
         let url = format!(
             "{}/select/{}/prometheus/api/v1/{}",
-            vm_url.url,
+            vm_url.select_url,
             user.id_victoria(db).await.unwrap(),
             path,
         );
@@ -71,19 +71,24 @@ mod victoria_api {
             if k.is_none() {
                 continue;
             }
+            // The backend authenticates on victoria metric with its own credentials.
+            if k == Some(http::header::AUTHORIZATION) && vm_url.has_credentials() {
+                continue;
+            }
             req = req.header(k.unwrap(), v);
         }
+        req = vm_url.authenticate(req);
 
         let res = req
             .send()
             .await
-            .or_else(|e| -> Result<reqwest::Response, _> {
+            .map_err(|e| {
                 error!("http error on VM query : {:?}", &e);
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                StatusCode::INTERNAL_SERVER_ERROR
             })?;
         error!("VM response : {:#?}", &res);
 
-        return Ok((res.status(), res.bytes().await.unwrap()));
+        Ok((res.status(), res.bytes().await.unwrap()))
     }
     pub async fn post(
         user: CurrentUser,
@@ -96,7 +101,7 @@ mod victoria_api {
     ) -> Result<(http::StatusCode, Bytes), http::StatusCode> {
         let url = format!(
             "{}/select/{}/prometheus/api/v1/{}",
-            vm_url.url,
+            vm_url.select_url,
             user.id_victoria(db).await.unwrap(),
             path,
         );
@@ -105,19 +110,24 @@ mod victoria_api {
             if k.is_none() {
                 continue;
             }
+            // The backend authenticates on victoria metric with its own credentials.
+            if k == Some(http::header::AUTHORIZATION) && vm_url.has_credentials() {
+                continue;
+            }
             req = req.header(k.unwrap(), v);
         }
+        req = vm_url.authenticate(req);
 
         let res = req
             .send()
             .await
-            .or_else(|e| -> Result<reqwest::Response, _> {
+            .map_err(|e| {
                 error!("http error on VM query : {:?}", &e);
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+                StatusCode::INTERNAL_SERVER_ERROR
             })?;
         error!("VM response : {:#?}", &res);
 
-        return Ok((res.status(), res.bytes().await.unwrap()));
+        Ok((res.status(), res.bytes().await.unwrap()))
     }
 }
 mod agent {
@@ -148,17 +158,15 @@ mod agent {
         .fetch_optional(&db)
         .await?;
         match agent {
-            Some(a) => {
-                return Ok((StatusCode::OK, Json(Some(a))));
-            }
-            None => return Ok((StatusCode::NOT_FOUND, Json(None))),
+            Some(a) => Ok((StatusCode::OK, Json(Some(a)))),
+            None => Ok((StatusCode::NOT_FOUND, Json(None))),
         }
     }
     pub async fn delete(
         Path(agent_id): Path<Uuid>,
         user: CurrentUser,
         State(db): State<sqlxPool<sqlx::Postgres>>,
-    ) -> Result<(http::StatusCode), AppError> {
+    ) -> Result<http::StatusCode, AppError> {
         // If no agent found (or agent for another company) -> 404
         let agent: Option<Agent> = sqlx::query_as::<_, Agent>(
             "
@@ -173,7 +181,7 @@ mod agent {
         .await?;
 
         match agent {
-            None => return Ok(StatusCode::NOT_FOUND),
+            None => Ok(StatusCode::NOT_FOUND),
             Some(a) => {
                 match sqlx::query(
                     "
@@ -185,17 +193,13 @@ mod agent {
                 .execute(&db)
                 .await
                 {
-                    Ok(_) => {
-                        return Ok(StatusCode::OK);
-                    }
+                    Ok(_) => Ok(StatusCode::OK),
                     Err(e) => {
-                        if let Some(db_err) = e.as_database_error() {
-                            if let Some(code) = db_err.code() {
-                                // 23505 = unique_violation
-                                if code == "23505" {
-                                    return Err(AppError::AlreadyUsed);
-                                }
-                            }
+                        // 23505 = unique_violation
+                        if let Some(db_err) = e.as_database_error()
+                            && db_err.code().is_some_and(|code| code == "23505")
+                        {
+                            return Err(AppError::AlreadyUsed);
                         }
                         // Other SQL or unexpected error
                         Err(e.into())
@@ -219,7 +223,7 @@ mod agent {
         .bind(user.id_company)
         .fetch_all(&db)
         .await?;
-        return Ok((StatusCode::OK, Json(agents)));
+        Ok((StatusCode::OK, Json(agents)))
     }
 
     pub async fn post(
@@ -227,7 +231,7 @@ mod agent {
         State(db): State<sqlxPool<sqlx::Postgres>>,
         extract::Json(new_agent): extract::Json<PubAgent>,
     ) -> Result<impl IntoResponse, AppError> {
-        if new_agent.name == "" {
+        if new_agent.name.is_empty() {
             return Err(AppError::EmptyArgument);
         }
         let result = sqlx::query(
@@ -244,13 +248,11 @@ mod agent {
         match result {
             Ok(_) => Ok((StatusCode::CREATED, Json("created agent."))),
             Err(e) => {
-                if let Some(db_err) = e.as_database_error() {
-                    if let Some(code) = db_err.code() {
-                        // 23505 = unique_violation
-                        if code == "23505" {
-                            return Err(AppError::AlreadyUsed);
-                        }
-                    }
+                // 23505 = unique_violation
+                if let Some(db_err) = e.as_database_error()
+                    && db_err.code().is_some_and(|code| code == "23505")
+                {
+                    return Err(AppError::AlreadyUsed);
                 }
                 // Other SQL or unexpected error
                 Err(e.into())
@@ -0,0 +1,267 @@
+use axum::{
+    Extension, Router,
+    extract::State,
+    http::{HeaderMap, StatusCode, header::CONTENT_ENCODING},
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use bytes::Bytes;
+use prost::Message;
+use sqlx::Pool as sqlxPool;
+
+use super::super::super::{
+    model::{Agent, AppError, VictoriaMetric},
+    web::{
+        App,
+        app::{IngestLimits, VictoriaEndpoint},
+    },
+};
+
+// Prometheus remote write protocol, used by Prometheus, vmagent and Grafana Agent.
+// Routes used by the agents, they must be behind the agent token validation.
+pub fn router() -> Router<App> {
+    Router::new().route("/api/v1/write", post(self::post::write))
+}
+
+// Messages of the remote write 1.0 protobuf (prometheus/prompb/remote.proto and types.proto),
+// only the fields needed for the samples, the others are skipped by prost.
+mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct WriteRequest {
+        #[prost(message, repeated, tag = "1")]
+        pub timeseries: Vec<TimeSeries>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TimeSeries {
+        #[prost(message, repeated, tag = "1")]
+        pub labels: Vec<Label>,
+        #[prost(message, repeated, tag = "2")]
+        pub samples: Vec<Sample>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Label {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(string, tag = "2")]
+        pub value: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Sample {
+        #[prost(double, tag = "1")]
+        pub value: f64,
+        #[prost(int64, tag = "2")]
+        pub timestamp: i64,
+    }
+}
+
+// Value Prometheus writes when a series disappears, a NaN with this exact bit pattern.
+const STALE_MARKER_BITS: u64 = 0x7ff0_0000_0000_0002;
+
+/// Decode a snappy compressed remote write request into `VictoriaMetric` records.
+/// Staleness markers are not samples and can't be sent in JSON, they are skipped without being reported.
+/// Other NaN and infinite values are kept, the validation rejects them with a reason like on the other routes.
+pub fn parse_write_request(
+    body: &[u8],
+    max_body_bytes: usize,
+) -> Result<Vec<VictoriaMetric>, AppError> {
+    let decoded_len =
+        snap::raw::decompress_len(body).map_err(|e| AppError::InvalidPayload(e.to_string()))?;
+    if decoded_len > max_body_bytes {
+        return Err(AppError::PayloadTooLarge);
+    }
+
+    let decoded = snap::raw::Decoder::new()
+        .decompress_vec(body)
+        .map_err(|e| AppError::InvalidPayload(e.to_string()))?;
+    let request = proto::WriteRequest::decode(&decoded[..])
+        .map_err(|e| AppError::InvalidPayload(e.to_string()))?;
+
+    let metrics = request
+        .timeseries
+        .into_iter()
+        .map(|series| {
+            let (values, timestamps) = series
+                .samples
+                .iter()
+                .filter(|sample| sample.value.to_bits() != STALE_MARKER_BITS)
+                .map(|sample| (sample.value, sample.timestamp))
+                .unzip();
+
+            VictoriaMetric {
+                metric: series
+                    .labels
+                    .into_iter()
+                    .map(|label| (label.name, label.value))
+                    .collect(),
+                values,
+                timestamps,
+            }
+        })
+        .filter(|metric| !metric.values.is_empty())
+        .collect();
+
+    Ok(metrics)
+}
+
+mod post {
+    use axum_login::tracing::debug;
+
+    use super::super::victoria_api::post::import_metrics;
+    use super::*;
+
+    pub async fn write(
+        Extension(agent): Extension<Agent>,
+        State(db): State<sqlxPool<sqlx::Postgres>>,
+        State(client): State<reqwest::Client>,
+        State(vm): State<VictoriaEndpoint>,
+        State(limits): State<IngestLimits>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Result<Response, AppError> {
+        let encoding = headers
+            .get(CONTENT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        if !encoding.eq_ignore_ascii_case("snappy") {
+            return Err(AppError::UnsupportedEncoding(encoding.to_string()));
+        }
+
+        let metrics = parse_write_request(&body, limits.max_body_bytes)?;
+        debug!(
+            "agent {} sent {} series with remote write",
+            agent.name,
+            metrics.len()
+        );
+        if metrics.is_empty() {
+            return Ok(StatusCode::NO_CONTENT.into_response());
+        }
+
+        let report = import_metrics(db, &client, &vm, &limits, &agent, metrics).await?;
+
+        Ok(report.into_response(StatusCode::NO_CONTENT))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label(name: &str, value: &str) -> proto::Label {
+        proto::Label {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    fn sample(value: f64, timestamp: i64) -> proto::Sample {
+        proto::Sample { value, timestamp }
+    }
+
+    fn body(timeseries: Vec<proto::TimeSeries>) -> Vec<u8> {
+        let request = proto::WriteRequest { timeseries }.encode_to_vec();
+        snap::raw::Encoder::new().compress_vec(&request).unwrap()
+    }
+
+    #[test]
+    fn series_become_metrics() {
+        let body = body(vec![
+            proto::TimeSeries {
+                labels: vec![
+                    label("__name__", "http_requests_total"),
+                    label("job", "node"),
+                    label("code", "200"),
+                ],
+                samples: vec![sample(1.0, 1000), sample(2.0, 2000)],
+            },
+            proto::TimeSeries {
+                labels: vec![label("__name__", "up")],
+                samples: vec![sample(1.0, 1000)],
+            },
+        ]);
+
+        let metrics = parse_write_request(&body, 1024).unwrap();
+
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(metrics[0].metric["__name__"], "http_requests_total");
+        assert_eq!(metrics[0].metric["job"], "node");
+        assert_eq!(metrics[0].metric["code"], "200");
+        assert_eq!(metrics[0].values, [1.0, 2.0]);
+        assert_eq!(metrics[0].timestamps, [1000, 2000]);
+        assert_eq!(metrics[1].metric["__name__"], "up");
+    }
+
+    #[test]
+    fn stale_markers_are_skipped() {
+        let stale = f64::from_bits(STALE_MARKER_BITS);
+        let body = body(vec![
+            proto::TimeSeries {
+                labels: vec![label("__name__", "up")],
+                samples: vec![sample(stale, 1000), sample(1.0, 2000)],
+            },
+            proto::TimeSeries {
+                labels: vec![label("__name__", "gone")],
+                samples: vec![sample(stale, 1000)],
+            },
+        ]);
+
+        let metrics = parse_write_request(&body, 1024).unwrap();
+
+        // A series left without samples is not sent.
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].values, [1.0]);
+        assert_eq!(metrics[0].timestamps, [2000]);
+    }
+
+    #[test]
+    fn non_finite_values_are_left_to_the_validation() {
+        let body = body(vec![proto::TimeSeries {
+            labels: vec![label("__name__", "ratio")],
+            samples: vec![
+                sample(f64::NAN, 1000),
+                sample(f64::INFINITY, 2000),
+                sample(f64::NEG_INFINITY, 3000),
+            ],
+        }]);
+
+        let metrics = parse_write_request(&body, 1024).unwrap();
+
+        assert_eq!(metrics[0].values.len(), 3);
+        assert!(metrics[0].values[0].is_nan());
+        assert_eq!(metrics[0].values[1], f64::INFINITY);
+        assert_eq!(metrics[0].timestamps, [1000, 2000, 3000]);
+    }
+
+    #[test]
+    fn decompressed_size_is_limited() {
+        let body = body(vec![proto::TimeSeries {
+            labels: vec![label("__name__", &"a".repeat(200))],
+            samples: vec![sample(1.0, 1000)],
+        }]);
+
+        assert!(matches!(
+            parse_write_request(&body, 100),
+            Err(AppError::PayloadTooLarge)
+        ));
+    }
+
+    #[test]
+    fn invalid_bodies() {
+        // Not snappy.
+        assert!(matches!(
+            parse_write_request(b"\xff\xff\xff\xff\xff", 1024),
+            Err(AppError::InvalidPayload(_))
+        ));
+
+        // Snappy, but not a write request.
+        let body = snap::raw::Encoder::new()
+            .compress_vec(b"\xff\xff\xff")
+            .unwrap();
+        assert!(matches!(
+            parse_write_request(&body, 1024),
+            Err(AppError::InvalidPayload(_))
+        ));
+    }
+}
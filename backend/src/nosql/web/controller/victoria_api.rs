@@ -21,16 +21,26 @@ pub fn router() -> Router<App> {
     //.route("/select", post(self::post::insert))
 }
 
-mod post {
+pub mod post {
+    use axum::response::Response;
     use axum::{
         Extension, debug_handler,
         extract::{self, State},
     };
-    use axum_login::tracing::debug;
+    use axum_login::tracing::{debug, error, info};
+    use bytes::Bytes;
     use serde::Serialize;
+    use std::time::{SystemTime, UNIX_EPOCH};
     use uuid::Uuid;
 
-    use crate::nosql::model::AppError;
+    use crate::nosql::{
+        model::{AppError, IngestReport},
+        validation::ValidationMode,
+        web::{
+            app::{IngestLimits, VictoriaEndpoint},
+            extractor::decoded_body::DecodedBody,
+        },
+    };
 
     use super::super::super::super::{
         model::{Agent, VictoriaMetric},
@@ -51,42 +61,184 @@ mod post {
         .bind(agent.id_company)
         .fetch_one(&db)
         .await?;
-        return Ok(agent_victoria_id.0);
+        Ok(agent_victoria_id.0)
+    }
+
+    /// Parse a body of `VictoriaMetric` records, either as a JSON array or as JSON lines.
+    pub fn parse_metrics(body: &[u8]) -> Result<Vec<VictoriaMetric>, AppError> {
+        let is_array = body
+            .iter()
+            .find(|b| !b.is_ascii_whitespace())
+            .is_some_and(|b| *b == b'[');
+
+        let metrics = if is_array {
+            serde_json::from_slice::<Vec<VictoriaMetric>>(body)
+        } else {
+            serde_json::Deserializer::from_slice(body)
+                .into_iter::<VictoriaMetric>()
+                .collect()
+        };
+
+        metrics.map_err(|e| AppError::InvalidPayload(e.to_string()))
     }
 
     pub async fn insert(
         Extension(agent): Extension<Agent>,
         State(db): State<sqlxPool<sqlx::Postgres>>,
         State(client): State<reqwest::Client>,
-        extract::Json(mut payload): extract::Json<VictoriaMetric>,
-    ) -> Result<http::StatusCode, AppError> {
+        State(vm): State<VictoriaEndpoint>,
+        State(limits): State<IngestLimits>,
+        DecodedBody(body): DecodedBody,
+    ) -> Result<Response, AppError> {
+        let metrics = parse_metrics(&body)?;
+        if metrics.is_empty() {
+            return Err(AppError::EmptyArgument);
+        }
+
+        let report = import_metrics(db, &client, &vm, &limits, &agent, metrics).await?;
+        Ok(report.into_response(StatusCode::OK))
+    }
+
+    /// Set the `job` label to the agent name, keeping a different job sent by the agent in `exported_job`.
+    pub(super) fn set_job(metric: &mut VictoriaMetric, job: &str) {
+        if let Some(exported) = metric.metric.insert("job".to_string(), job.to_string())
+            && exported != job
+        {
+            metric.metric.insert("exported_job".to_string(), exported);
+        }
+    }
+
+    /// Send metrics to the VictoriaMetrics tenant of the agent company, with the `job` label set to the agent name.
+    /// Every ingestion route ends here, whatever the format sent by the agent. Like Prometheus, a different `job`
+    /// sent by the agent (the one of a remote write client, for example) is kept in `exported_job`.
+    ///
+    /// The samples are checked against the validation rules first. Depending on the mode, invalid samples are dropped
+    /// and listed in the returned report, or the whole request is refused with a 400 listing them.
+    ///
+    /// Failures of VictoriaMetrics are returned to the agent: unreachable or overloaded gives 503, other server
+    /// errors 502, both with `Retry-After`. Payloads refused by VictoriaMetrics give 400, they must not be sent again.
+    pub async fn import_metrics(
+        db: sqlxPool<sqlx::Postgres>,
+        client: &reqwest::Client,
+        vm: &VictoriaEndpoint,
+        limits: &IngestLimits,
+        agent: &Agent,
+        mut metrics: Vec<VictoriaMetric>,
+    ) -> Result<IngestReport, AppError> {
+        for metric in metrics.iter_mut() {
+            set_job(metric, &agent.name);
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+        let (metrics, report) = limits.validation.validate(metrics, now);
+        if report.rejected_samples > 0 {
+            info!(
+                "agent {} sent {} invalid samples, first reason : {:?}",
+                agent.name,
+                report.rejected_samples,
+                report.rejected.first()
+            );
+            if limits.validation.mode == ValidationMode::Reject {
+                return Err(AppError::InvalidSamples(report));
+            }
+        }
+        if metrics.is_empty() {
+            return Ok(report);
+        }
+
         let url = format!(
-            "http://vmauth:8427/insert/{}/prometheus/api/v1/import",
-            get_victoria_tenant_from_agent(db, &agent).await?,
+            "{}/insert/{}/prometheus/api/v1/import",
+            vm.insert_url,
+            get_victoria_tenant_from_agent(db, agent).await?,
         );
+
+        // VictoriaMetrics import expect one json object per line.
+        let mut payload = String::new();
+        for metric in metrics.iter() {
+            payload.push_str(&serde_json::to_string(metric).unwrap());
+            payload.push('\n');
+        }
         debug!(
-            "trying to request url {} with body {}",
+            "trying to request url {} with {} metrics",
             url,
-            serde_json::to_string(&payload).unwrap()
+            metrics.len()
         );
-        payload.metric.insert("job".to_string(), agent.name.clone());
 
-        let req = client
-            .post(url)
-            .basic_auth("foo", Some("bar"))
+        let req = vm
+            .authenticate(client.post(url))
             .header("Content-Type", "application/json")
-            .body(serde_json::to_string(&payload).unwrap());
-        let res = req
-            .send()
-            .await
-            .or_else(|e| -> Result<reqwest::Response, _> {
-                println!("err : {:?}", &e);
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
-            });
+            .body(payload);
+        let res = req.send().await.map_err(|e| {
+            error!("could not reach VictoriaMetrics : {:?}", e);
+            AppError::UpstreamUnavailable("could not reach the storage".to_string())
+        })?;
         debug!("sent a post request, result : {:?}", res);
-        return Ok(StatusCode::OK);
+
+        let status = res.status();
+        if status.is_success() {
+            return Ok(report);
+        }
+
+        let reason = res.text().await.unwrap_or_default().trim().to_string();
+        error!(
+            "VictoriaMetrics answered {} for agent {} : {}",
+            status, agent.name, reason
+        );
+        Err(match status {
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+                AppError::UpstreamUnavailable(reason)
+            }
+            // Wrong credentials are a problem of the backend, not of the payload.
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                AppError::UpstreamFailed(format!("storage answered {}", status))
+            }
+            status if status.is_client_error() => AppError::UpstreamRejected(reason),
+            status => AppError::UpstreamFailed(format!("storage answered {}: {}", status, reason)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::post::set_job;
+    use crate::nosql::model::VictoriaMetric;
+
+    fn metric(labels: &[(&str, &str)]) -> VictoriaMetric {
+        VictoriaMetric {
+            metric: labels
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<HashMap<_, _>>(),
+            values: vec![1.0],
+            timestamps: vec![0],
+        }
     }
-    pub async fn select(auth_session: AuthSession, messages: Messages) -> impl IntoResponse {
-        "html message from page 2".into_response()
+
+    #[test]
+    fn job_is_the_agent_name() {
+        let mut up = metric(&[("__name__", "up")]);
+        set_job(&mut up, "agent");
+
+        assert_eq!(up.metric["job"], "agent");
+        assert!(!up.metric.contains_key("exported_job"));
+    }
+
+    #[test]
+    fn client_job_is_exported() {
+        let mut up = metric(&[("__name__", "up"), ("job", "node")]);
+        set_job(&mut up, "agent");
+
+        assert_eq!(up.metric["job"], "agent");
+        assert_eq!(up.metric["exported_job"], "node");
+
+        let mut up = metric(&[("__name__", "up"), ("job", "agent")]);
+        set_job(&mut up, "agent");
+
+        assert!(!up.metric.contains_key("exported_job"));
     }
 }
@@ -10,6 +10,8 @@ use sqlx::Pool as sqlxPool;
 use uuid::Uuid;
 #[derive(Debug)]
 pub struct CurrentUser {
+    // No handler needs the user itself yet, only its company.
+    #[allow(dead_code)]
     pub id: Uuid,
     pub id_company: Uuid,
 }
@@ -25,7 +27,7 @@ impl CurrentUser {
         .bind(self.id_company)
         .fetch_one(&db)
         .await?;
-        return Ok(id_victoria.0);
+        Ok(id_victoria.0)
     }
 }
 impl<S> FromRequestParts<S> for CurrentUser
@@ -0,0 +1,87 @@
+use std::io::Read;
+
+use axum::{
+    extract::{FromRef, FromRequest, Request},
+    http::header::CONTENT_ENCODING,
+};
+use axum_login::tracing::error;
+use bytes::Bytes;
+
+use super::super::super::{model::AppError, web::app::IngestLimits};
+
+// Body of a request, decompressed according to its Content-Encoding header (gzip or zstd).
+// The decompressed size is limited by `IngestLimits`, so a small compressed body can't fill the memory.
+#[derive(Debug)]
+pub struct DecodedBody(pub Bytes);
+
+impl<S> FromRequest<S> for DecodedBody
+where
+    S: Send + Sync,
+    IngestLimits: FromRef<S>,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let limits = IngestLimits::from_ref(state);
+        let encoding = req
+            .headers()
+            .get(CONTENT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("identity")
+            .trim()
+            .to_lowercase();
+
+        let body = Bytes::from_request(req, state).await.map_err(|e| {
+            if e.status() == http::StatusCode::PAYLOAD_TOO_LARGE {
+                AppError::PayloadTooLarge
+            } else {
+                AppError::InvalidPayload(e.body_text())
+            }
+        })?;
+
+        if matches!(encoding.as_str(), "" | "identity") {
+            if body.len() > limits.max_body_bytes {
+                return Err(AppError::PayloadTooLarge);
+            }
+            return Ok(Self(body));
+        }
+
+        // Decompression is CPU bound, it runs outside of the async workers.
+        let decoded =
+            tokio::task::spawn_blocking(move || decode(&body, &encoding, limits.max_body_bytes))
+                .await
+                .map_err(|e| {
+                    error!("body decompression failed : {:?}", e);
+                    AppError::Internal("body decompression failed".to_string())
+                })??;
+
+        Ok(Self(Bytes::from(decoded)))
+    }
+}
+
+fn decode(body: &[u8], encoding: &str, limit: usize) -> Result<Vec<u8>, AppError> {
+    match encoding {
+        "gzip" | "x-gzip" => read_limited(flate2::read::GzDecoder::new(body), limit),
+        "zstd" => {
+            let decoder = zstd::stream::read::Decoder::new(body)
+                .map_err(|e| AppError::InvalidPayload(e.to_string()))?;
+            read_limited(decoder, limit)
+        }
+        _ => Err(AppError::UnsupportedEncoding(encoding.to_string())),
+    }
+}
+
+// Read at most one byte more than the limit, to know it was exceeded without decompressing everything.
+fn read_limited(reader: impl Read, limit: usize) -> Result<Vec<u8>, AppError> {
+    let mut decoded = Vec::new();
+    reader
+        .take(limit as u64 + 1)
+        .read_to_end(&mut decoded)
+        .map_err(|e| AppError::InvalidPayload(e.to_string()))?;
+
+    if decoded.len() > limit {
+        return Err(AppError::PayloadTooLarge);
+    }
+
+    Ok(decoded)
+}
@@ -49,12 +49,9 @@ pub async fn check_api_token_against_agent_table(
         Ok(a) => {
             debug!("found agent {} in db.", a.name);
             req.extensions_mut().insert(a);
-            return Ok(next.run(req).await);
-        }
-        Err(e) => {
-            return Err(StatusCode::UNAUTHORIZED);
+            // If agent exist, proceed to the next handler
+            Ok(next.run(req).await)
         }
+        Err(e) => Err(StatusCode::UNAUTHORIZED),
     }
-
-    // If agent exist, proceed to the next handler
 }